@@ -0,0 +1,503 @@
+// Static analysis of a ROM: finds the reachable code by following jumps,
+// calls and skips from where the program starts, splits it into basic blocks
+// and functions, and tells code from data.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::opcode::Opcode;
+
+// How control leaves a basic block
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Exit {
+    // To the successors, by running on, jumping or skipping
+    Next,
+    Return,
+    // JP V0, the targets depend on V0 and aren't followed
+    Computed,
+    // An invalid instruction or the end of the ROM
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: u16,
+    // The address after the last instruction
+    pub end: u16,
+    pub successors: Vec<u16>,
+    pub exit: Exit,
+}
+
+// The blocks reachable from a CALL target, or from the entry point, without
+// following calls
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub entry: u16,
+    pub blocks: BTreeSet<u16>,
+    pub callees: BTreeSet<u16>,
+}
+
+pub struct Analysis {
+    rom: Vec<u8>,
+    load_address: u16,
+    instructions: BTreeMap<u16, Opcode>,
+    blocks: BTreeMap<u16, Block>,
+    functions: BTreeMap<u16, Function>,
+    computed_jumps: BTreeSet<u16>,
+    // Addresses control reaches that don't hold a valid instruction
+    invalid: BTreeSet<u16>,
+}
+
+impl Analysis {
+    // Analyze a ROM loaded at the given address, which is also where it starts
+    pub fn new(rom: &[u8], load_address: u16) -> Analysis {
+        let mut analysis = Analysis {
+            rom: rom.to_vec(),
+            load_address,
+            instructions: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            computed_jumps: BTreeSet::new(),
+            invalid: BTreeSet::new(),
+        };
+
+        let (leaders, entries, calls) = analysis.trace();
+        analysis.build_blocks(&leaders);
+        analysis.build_functions(&entries, &calls);
+
+        analysis
+    }
+
+    // Walk every path from the entry point, returning the addresses blocks
+    // have to start at, the function entries and the calls as (site, target)
+    fn trace(&mut self) -> (BTreeSet<u16>, BTreeSet<u16>, BTreeSet<(u16, u16)>) {
+        let entry = self.load_address;
+        let mut leaders = BTreeSet::new();
+        let mut entries = BTreeSet::new();
+        let mut calls = BTreeSet::new();
+        leaders.insert(entry);
+        entries.insert(entry);
+
+        let mut pending = vec![entry];
+        while let Some(address) = pending.pop() {
+            if self.instructions.contains_key(&address) || self.invalid.contains(&address) {
+                continue;
+            }
+
+            let opcode = match self.fetch(address) {
+                Some(Opcode::UNDEFINED) | None => {
+                    self.invalid.insert(address);
+                    continue;
+                }
+                Some(opcode) => opcode,
+            };
+            self.instructions.insert(address, opcode);
+
+            match opcode {
+                Opcode::CALL(target) => {
+                    calls.insert((address, target));
+                    if entries.insert(target) {
+                        leaders.insert(target);
+                        pending.push(target);
+                    }
+                }
+                Opcode::JP_V(_) => {
+                    self.computed_jumps.insert(address);
+                }
+                _ => {}
+            }
+
+            let successors = successors(opcode, address);
+            // Anywhere but straight on starts a new block
+            if successors != [address.wrapping_add(2)] {
+                leaders.extend(&successors);
+            }
+            pending.extend(successors);
+        }
+
+        (leaders, entries, calls)
+    }
+
+    fn fetch(&self, address: u16) -> Option<Opcode> {
+        let offset = address.checked_sub(self.load_address)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+
+        Some(Opcode::from_bytes((bytes[0] as u16) << 8 | bytes[1] as u16))
+    }
+
+    // Group the instructions into runs that are only entered at the top and
+    // only leave at the bottom
+    fn build_blocks(&mut self, leaders: &BTreeSet<u16>) {
+        // Follow each block from its start rather than going through the
+        // instructions in order, code jumped into at an odd address runs
+        // alongside the code around it
+        let mut pending: Vec<u16> = leaders.iter().cloned().collect();
+
+        while let Some(start) = pending.pop() {
+            if self.blocks.contains_key(&start) {
+                continue;
+            }
+
+            let mut address = start;
+            while let Some(&opcode) = self.instructions.get(&address) {
+                let next = address.wrapping_add(2);
+                let successors = successors(opcode, address);
+
+                let exit = match opcode {
+                    Opcode::RET => Some(Exit::Return),
+                    Opcode::JP_V(_) => Some(Exit::Computed),
+                    _ if self.invalid.contains(&next) && successors == [next] => Some(Exit::Invalid),
+                    _ if successors != [next] => Some(Exit::Next),
+                    // Runs into another block, or is jumped into halfway
+                    // through, or runs into code that's only reached some
+                    // other way
+                    _ if (1..=2).any(|i| leaders.contains(&address.wrapping_add(i))) => Some(Exit::Next),
+                    _ if !self.instructions.contains_key(&next) => Some(Exit::Next),
+                    _ => None,
+                };
+
+                if let Some(exit) = exit {
+                    let successors = if exit == Exit::Next { successors } else { Vec::new() };
+                    pending.extend(&successors);
+                    self.blocks.insert(start, Block { start, end: next, successors, exit });
+                    break;
+                }
+                address = next;
+            }
+        }
+    }
+
+    fn build_functions(&mut self, entries: &BTreeSet<u16>, calls: &BTreeSet<(u16, u16)>) {
+        for &entry in entries {
+            let mut blocks = BTreeSet::new();
+            let mut pending = vec![entry];
+
+            while let Some(start) = pending.pop() {
+                if let Some(block) = self.blocks.get(&start) {
+                    if blocks.insert(start) {
+                        pending.extend(&block.successors);
+                    }
+                }
+            }
+
+            let callees = calls.iter()
+                .filter(|&&(site, _)| blocks.iter().any(|start| {
+                    let block = &self.blocks[start];
+                    site >= block.start && site < block.end
+                }))
+                .map(|&(_, target)| target)
+                .collect();
+
+            self.functions.insert(entry, Function { entry, blocks, callees });
+        }
+    }
+
+    pub fn blocks(&self) -> &BTreeMap<u16, Block> {
+        &self.blocks
+    }
+
+    pub fn functions(&self) -> &BTreeMap<u16, Function> {
+        &self.functions
+    }
+
+    // Where JP V0 is used, control flow there is unknown
+    pub fn computed_jumps(&self) -> &BTreeSet<u16> {
+        &self.computed_jumps
+    }
+
+    pub fn invalid(&self) -> &BTreeSet<u16> {
+        &self.invalid
+    }
+
+    // Whether an address is part of a reachable instruction
+    pub fn is_code(&self, address: u16) -> bool {
+        self.instructions.contains_key(&address) || self.instructions.contains_key(&address.wrapping_sub(1))
+    }
+
+    // The instruction starting at an address, disassembled
+    pub fn disassemble(&self, address: u16) -> Option<String> {
+        self.instructions.get(&address).map(Opcode::to_string)
+    }
+
+    // sub_XXX for subroutines, L_XXX for other blocks
+    pub fn label(&self, address: u16) -> Option<String> {
+        if self.functions.contains_key(&address) {
+            Some(format!("sub_{:03X}", address))
+        } else if self.blocks.contains_key(&address) {
+            Some(format!("L_{:03X}", address))
+        } else {
+            None
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let start = self.load_address as usize;
+        let code = (start..start + self.rom.len())
+            .filter(|&address| address <= u16::MAX as usize && self.is_code(address as u16))
+            .count();
+
+        let mut text = format!(
+            "{} instructions in {} blocks and {} functions, {} bytes of code and {} of data\n",
+            self.instructions.len(), self.blocks.len(), self.functions.len(), code, self.rom.len() - code);
+        for address in &self.computed_jumps {
+            writeln!(text, "Computed jump at 0x{:03X}, its targets aren't known", address).unwrap();
+        }
+        for address in &self.invalid {
+            writeln!(text, "No valid instruction at 0x{:03X}", address).unwrap();
+        }
+
+        text
+    }
+
+    // The whole ROM as labelled code and data, with the address, the bytes
+    // and the instruction on each line
+    pub fn listing(&self) -> String {
+        let mut text = String::new();
+        let end = self.load_address as usize + self.rom.len();
+        let mut address = self.load_address as usize;
+
+        while address < end {
+            let offset = address - self.load_address as usize;
+
+            if let Some(opcode) = self.instructions.get(&(address as u16)) {
+                if let Some(label) = self.label(address as u16) {
+                    writeln!(text, "{}:", label).unwrap();
+                }
+                let comment = match opcode {
+                    Opcode::JP_V(_) => "  ; computed jump",
+                    _ => "",
+                };
+                writeln!(text, "    0x{:03X}  {:02X}{:02X}  {}{}",
+                    address, self.rom[offset], self.rom[offset + 1], opcode, comment).unwrap();
+                address += 2;
+            } else {
+                // Up to 8 bytes of data a line, up to the next instruction
+                let mut bytes = Vec::new();
+                while address + bytes.len() < end && bytes.len() < 8
+                    && !self.instructions.contains_key(&((address + bytes.len()) as u16)) {
+                    bytes.push(format!("0x{:02X}", self.rom[offset + bytes.len()]));
+                }
+                writeln!(text, "    0x{:03X}  DB {}", address, bytes.join(", ")).unwrap();
+                address += bytes.len();
+            }
+        }
+
+        text
+    }
+
+    // The control flow graph in Graphviz DOT, one box of instructions per
+    // block grouped by function. Calls are dashed, computed jumps red.
+    pub fn cfg_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        // Blocks shared by functions go with the first
+        let mut placed = BTreeSet::new();
+        for function in self.functions.values() {
+            writeln!(dot, "    subgraph cluster_{:03X} {{\n        label=\"sub_{:03X}\";", function.entry, function.entry).unwrap();
+            for &start in &function.blocks {
+                if placed.insert(start) {
+                    writeln!(dot, "        {};", self.block_node(&self.blocks[&start])).unwrap();
+                }
+            }
+            dot += "    }\n";
+        }
+
+        for block in self.blocks.values() {
+            if placed.insert(block.start) {
+                writeln!(dot, "    {};", self.block_node(block)).unwrap();
+            }
+            for successor in &block.successors {
+                writeln!(dot, "    b{:03X} -> b{:03X};", block.start, successor).unwrap();
+            }
+            let calls = self.instructions.range(block.start..block.end).filter_map(|(_, opcode)| match opcode {
+                Opcode::CALL(target) => Some(*target),
+                _ => None,
+            });
+            for target in calls {
+                if self.blocks.contains_key(&target) {
+                    writeln!(dot, "    b{:03X} -> b{:03X} [style=dashed];", block.start, target).unwrap();
+                }
+            }
+        }
+
+        dot += "}\n";
+        dot
+    }
+
+    fn block_node(&self, block: &Block) -> String {
+        let mut label = String::new();
+        for (address, opcode) in self.instructions.range(block.start..block.end) {
+            write!(label, "0x{:03X}  {}\\l", address, opcode).unwrap();
+        }
+
+        let color = match block.exit {
+            Exit::Computed | Exit::Invalid => ", color=red",
+            _ => "",
+        };
+        format!("b{:03X} [label=\"{}\"{}]", block.start, label, color)
+    }
+
+    // Which functions call which in Graphviz DOT, functions with computed
+    // jumps are red
+    pub fn call_graph_dot(&self) -> String {
+        let mut dot = String::from("digraph calls {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        for function in self.functions.values() {
+            let computed = function.blocks.iter().any(|start| self.blocks[start].exit == Exit::Computed);
+            let color = if computed { ", color=red" } else { "" };
+            writeln!(dot, "    f{:03X} [label=\"sub_{:03X}\"{}];", function.entry, function.entry, color).unwrap();
+        }
+        for function in self.functions.values() {
+            for callee in &function.callees {
+                writeln!(dot, "    f{:03X} -> f{:03X};", function.entry, callee).unwrap();
+            }
+        }
+
+        dot += "}\n";
+        dot
+    }
+}
+
+// Where control can go after an instruction, not counting calls
+fn successors(opcode: Opcode, address: u16) -> Vec<u16> {
+    let next = address.wrapping_add(2);
+
+    match opcode {
+        Opcode::JP(target) => vec![target],
+        Opcode::RET | Opcode::JP_V(_) | Opcode::UNDEFINED => vec![],
+        Opcode::SE(..) | Opcode::SNE(..) | Opcode::SE_V(..) | Opcode::SNE_V(..) |
+        Opcode::SKP(_) | Opcode::SKNP(_) => vec![next, next.wrapping_add(2)],
+        _ => vec![next],
+    }
+}
+
+
+#[cfg(test)]
+fn assemble(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes().to_vec()).collect()
+}
+
+#[test]
+fn test_blocks() {
+    let rom = assemble(&[
+        0x6000, // 200: LD V0, 0
+        0x3005, // 202: SE V0, 5
+        0x1208, // 204: JP 208
+        0x7001, // 206: ADD V0, 1
+        0x1202, // 208: JP 202
+    ]);
+    let analysis = Analysis::new(&rom, 0x200);
+
+    let starts: Vec<u16> = analysis.blocks().keys().cloned().collect();
+    assert_eq!(starts, vec![0x200, 0x202, 0x204, 0x206, 0x208]);
+    assert_eq!(analysis.blocks()[&0x200].successors, vec![0x202]);
+    assert_eq!(analysis.blocks()[&0x202].successors, vec![0x204, 0x206]);
+    assert_eq!(analysis.blocks()[&0x206], Block { start: 0x206, end: 0x208, successors: vec![0x208], exit: Exit::Next });
+    assert_eq!(analysis.functions().len(), 1);
+}
+
+#[test]
+fn test_misaligned_jump() {
+    let rom = assemble(&[
+        0x3000, // 200: SE V0, 0
+        0x1205, // 202: JP 205
+        0x6100, // 204: LD V1, 0, and 205: RET
+        0xEE9E, // 206: SKP VE
+        0x00EE, // 208: RET
+        0x00EE, // 20A: RET
+    ]);
+    let analysis = Analysis::new(&rom, 0x200);
+
+    let starts: Vec<u16> = analysis.blocks().keys().cloned().collect();
+    assert_eq!(starts, vec![0x200, 0x202, 0x204, 0x205, 0x206, 0x208, 0x20A]);
+    assert_eq!(analysis.blocks()[&0x204], Block { start: 0x204, end: 0x206, successors: vec![0x206], exit: Exit::Next });
+    assert_eq!(analysis.blocks()[&0x205], Block { start: 0x205, end: 0x207, successors: vec![], exit: Exit::Return });
+    assert_eq!(analysis.blocks()[&0x206].successors, vec![0x208, 0x20A]);
+}
+
+#[test]
+fn test_code_and_data() {
+    let rom = assemble(&[
+        0xA206, // 200: LD I, 206
+        0xD015, // 202: DRW V0, V1, 5
+        0x1204, // 204: JP 204
+        0xF090, // 206: sprite data
+        0x90F0,
+    ]);
+    let analysis = Analysis::new(&rom, 0x200);
+
+    assert!(analysis.is_code(0x205));
+    assert!(!analysis.is_code(0x206));
+    assert!(analysis.listing().contains("0x206  DB 0xF0, 0x90, 0x90, 0xF0"));
+    assert!(analysis.summary().starts_with("3 instructions in 2 blocks and 1 functions, 6 bytes of code and 4 of data"));
+
+    // Files that run past the end of the address space don't overflow it
+    let mut rom = rom;
+    rom.resize(0x10000, 0);
+    assert!(Analysis::new(&rom, 0x200).summary().contains("6 bytes of code"));
+}
+
+#[test]
+fn test_calls() {
+    let rom = assemble(&[
+        0x2206, // 200: CALL 206
+        0x220A, // 202: CALL 20A
+        0x1204, // 204: JP 204
+        0x220A, // 206: CALL 20A
+        0x00EE, // 208: RET
+        0x00E0, // 20A: CLS
+        0x00EE, // 20C: RET
+    ]);
+    let analysis = Analysis::new(&rom, 0x200);
+
+    let functions = analysis.functions();
+    assert_eq!(functions.keys().cloned().collect::<Vec<_>>(), vec![0x200, 0x206, 0x20A]);
+    assert_eq!(functions[&0x200].callees, [0x206, 0x20A].iter().cloned().collect());
+    assert_eq!(functions[&0x206].callees, [0x20A].iter().cloned().collect());
+    assert!(functions[&0x20A].callees.is_empty());
+    assert_eq!(analysis.blocks()[&0x20A].exit, Exit::Return);
+
+    let dot = analysis.call_graph_dot();
+    assert!(dot.contains("f200 -> f206;"));
+    assert!(dot.contains("f206 -> f20A;"));
+    assert!(analysis.listing().contains("sub_20A:\n    0x20A  00E0  CLS\n"));
+}
+
+#[test]
+fn test_computed_jump() {
+    let rom = assemble(&[
+        0xB204, // 200: JP V0, 204
+        0x1202, // 202: JP 202
+        0x1204, // 204: JP 204
+    ]);
+    let analysis = Analysis::new(&rom, 0x200);
+
+    assert_eq!(analysis.computed_jumps(), &[0x200].iter().cloned().collect());
+    assert_eq!(analysis.blocks()[&0x200].exit, Exit::Computed);
+    // The targets aren't followed
+    assert!(!analysis.is_code(0x204));
+    assert!(analysis.cfg_dot().contains("b200 [label=\"0x200  JP V0, 0x204\\l\", color=red]"));
+}
+
+#[test]
+fn test_invalid() {
+    // Runs off the end of the ROM
+    let analysis = Analysis::new(&assemble(&[0x6000, 0xFFFF]), 0x200);
+    assert_eq!(analysis.invalid(), &[0x202].iter().cloned().collect());
+    assert_eq!(analysis.blocks()[&0x200].exit, Exit::Invalid);
+
+    let analysis = Analysis::new(&assemble(&[0x6000]), 0x200);
+    assert_eq!(analysis.invalid(), &[0x202].iter().cloned().collect());
+    assert_eq!(analysis.blocks()[&0x200].exit, Exit::Invalid);
+}
+
+#[test]
+fn test_cfg_dot() {
+    let rom = assemble(&[0x3000, 0x1200, 0x1200]);
+    let dot = Analysis::new(&rom, 0x200).cfg_dot();
+
+    assert!(dot.starts_with("digraph cfg {"));
+    assert!(dot.contains("subgraph cluster_200"));
+    assert!(dot.contains("b200 -> b202;"));
+    assert!(dot.contains("b200 -> b204;"));
+}
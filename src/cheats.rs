@@ -0,0 +1,411 @@
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use crate::chip8::Chip8;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Kind {
+    // Written once when the ROM starts
+    Poke,
+    // Written before every frame, so the game can't change it
+    Freeze,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    pub kind: Kind,
+    pub address: u16,
+    pub value: u8,
+    // What it does, e.g. "Infinite lives"
+    pub name: String,
+}
+
+impl Cheat {
+    // A line like `freeze 0x2F0 3 Infinite lives`, the same as the console
+    // command
+    pub fn parse(line: &str) -> Result<Cheat, String> {
+        let mut words = line.split_whitespace();
+        let kind = match words.next() {
+            Some("poke") => Kind::Poke,
+            Some("freeze") => Kind::Freeze,
+            _ => return Err(format!("expected poke or freeze: {}", line)),
+        };
+
+        let address = words.next().and_then(parse_number).filter(|&address| address < 4096)
+            .ok_or_else(|| format!("invalid address: {}", line))?;
+        let value = words.next().and_then(parse_number).filter(|&value| value < 256)
+            .ok_or_else(|| format!("invalid value: {}", line))?;
+        let name = words.collect::<Vec<_>>().join(" ");
+
+        Ok(Cheat { kind, address: address as u16, value: value as u8, name })
+    }
+
+    pub fn to_line(&self) -> String {
+        let kind = match self.kind {
+            Kind::Poke => "poke",
+            Kind::Freeze => "freeze",
+        };
+
+        format!("{} 0x{:03X} 0x{:02X} {}", kind, self.address, self.value, self.name).trim_end().to_string()
+    }
+}
+
+// The cheats for one ROM, kept in a text file named after its SHA-1 with
+// one cheat per line and `#` comments
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats { cheats: Vec::new() }
+    }
+
+    pub fn parse(text: &str) -> Result<Cheats, String> {
+        let mut cheats = Cheats::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if !line.is_empty() {
+                let cheat = Cheat::parse(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+                cheats.add(cheat);
+            }
+        }
+
+        Ok(cheats)
+    }
+
+    // Load a ROM's cheats, there are none if the file doesn't exist
+    pub fn load(path: &Path) -> Result<Cheats, String> {
+        if !path.exists() {
+            return Ok(Cheats::new());
+        }
+
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read cheats {}: {}", path.display(), e))?;
+
+        Cheats::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("could not create {}: {}", dir.display(), e))?;
+        }
+
+        fs::write(path, self.to_text()).map_err(|e| format!("could not write cheats {}: {}", path.display(), e))
+    }
+
+    pub fn to_text(&self) -> String {
+        self.cheats.iter().map(|cheat| cheat.to_line() + "\n").collect()
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    // Add a cheat, replacing any other for the same address
+    pub fn add(&mut self, cheat: Cheat) {
+        self.remove(cheat.address);
+        self.cheats.push(cheat);
+    }
+
+    // Returns whether there was a cheat for the address
+    pub fn remove(&mut self, address: u16) -> bool {
+        let count = self.cheats.len();
+        self.cheats.retain(|cheat| cheat.address != address);
+
+        self.cheats.len() != count
+    }
+
+    // Write every cheat, when the ROM is loaded
+    pub fn apply(&self, emu: &mut Chip8) {
+        for cheat in &self.cheats {
+            emu.poke(cheat.address, cheat.value);
+        }
+    }
+
+    // Write the frozen values, before every frame
+    pub fn freeze(&self, emu: &mut Chip8) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.kind == Kind::Freeze) {
+            emu.poke(cheat.address, cheat.value);
+        }
+    }
+}
+
+// Where cheats are kept, `chip8/cheats` in the XDG config directory
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chip8").join("cheats"))
+}
+
+pub fn file_name(dir: &Path, sha1: &str) -> PathBuf {
+    dir.join(format!("{}.txt", sha1))
+}
+
+// How a byte has to compare with the last snapshot to stay a candidate
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Compare {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+// Narrows down where a game keeps something like the lives, by comparing
+// memory with a snapshot again and again
+pub struct Search {
+    snapshot: [u8; 4096],
+    candidates: Vec<u16>,
+}
+
+impl Search {
+    // Every address is a candidate to start with
+    pub fn new(memory: &[u8; 4096]) -> Search {
+        Search {
+            snapshot: *memory,
+            candidates: (0..4096).collect(),
+        }
+    }
+
+    // Keep the candidates that compare, then take a new snapshot
+    pub fn narrow(&mut self, memory: &[u8; 4096], compare: Compare) {
+        let snapshot = &self.snapshot;
+
+        self.candidates.retain(|&address| {
+            let (old, new) = (snapshot[address as usize], memory[address as usize]);
+            match compare {
+                Compare::Equal(value) => new == value,
+                Compare::Changed => new != old,
+                Compare::Unchanged => new == old,
+                Compare::Increased => new > old,
+                Compare::Decreased => new < old,
+            }
+        });
+        self.snapshot = *memory;
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+// How many candidates are listed after a search
+const MAX_LISTED: usize = 16;
+
+const HELP: &str = "\
+new                       start a search, every address is a candidate
+eq N|changed|same|inc|dec keep the candidates equal to N, or that changed etc. since the last search
+list                      show the candidates and cheats
+poke ADDR N [NAME]        write a byte once, and again whenever the ROM is loaded
+freeze ADDR N [NAME]      write a byte before every frame
+remove ADDR               remove the cheat for an address
+save                      save the cheats for this ROM";
+
+// Commands typed in to search memory and manage cheats
+pub struct Console {
+    cheats: Cheats,
+    search: Option<Search>,
+    // Where `save` writes the cheats
+    path: PathBuf,
+}
+
+impl Console {
+    pub fn new(cheats: Cheats, path: PathBuf) -> Console {
+        Console { cheats, search: None, path }
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    // Run a command, returning what to print
+    pub fn execute(&mut self, line: &str, emu: &mut Chip8) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let compare = match words.next() {
+            None => return Ok(String::new()),
+            Some("help") => return Ok(HELP.to_string()),
+            Some("new") => {
+                self.search = Some(Search::new(emu.memory()));
+                return Ok(String::from("4096 candidates"));
+            }
+            Some("list") => return Ok(self.list()),
+            Some("save") => {
+                self.cheats.save(&self.path)?;
+                return Ok(format!("Cheats saved to {}", self.path.display()));
+            }
+            Some("remove") => {
+                let address = words.next().and_then(parse_number)
+                    .ok_or_else(|| format!("invalid address: {}", line))?;
+                return if self.cheats.remove(address as u16) {
+                    Ok(format!("Removed the cheat for 0x{:03X}", address))
+                } else {
+                    Err(format!("no cheat for 0x{:03X}", address))
+                };
+            }
+            Some("poke") | Some("freeze") => {
+                let cheat = Cheat::parse(line)?;
+                emu.poke(cheat.address, cheat.value);
+                let output = cheat.to_line();
+                self.cheats.add(cheat);
+                return Ok(output);
+            }
+            Some("eq") => {
+                let value = words.next().and_then(parse_number).filter(|&value| value < 256)
+                    .ok_or_else(|| format!("invalid value: {}", line))?;
+                Compare::Equal(value as u8)
+            }
+            Some("changed") => Compare::Changed,
+            Some("same") => Compare::Unchanged,
+            Some("inc") => Compare::Increased,
+            Some("dec") => Compare::Decreased,
+            Some(command) => return Err(format!("unknown command {}, try help", command)),
+        };
+
+        let search = self.search.as_mut().ok_or("no search yet, start one with new")?;
+        search.narrow(emu.memory(), compare);
+
+        Ok(self.list_candidates(emu.memory()))
+    }
+
+    fn list(&self) -> String {
+        let mut lines = Vec::new();
+
+        if let Some(ref search) = self.search {
+            lines.push(self.list_candidates(&search.snapshot));
+        }
+        lines.extend(self.cheats.cheats().iter().map(Cheat::to_line));
+
+        lines.join("\n")
+    }
+
+    fn list_candidates(&self, memory: &[u8; 4096]) -> String {
+        let candidates = match self.search {
+            Some(ref search) => search.candidates(),
+            None => return String::new(),
+        };
+
+        let mut text = match candidates.len() {
+            1 => String::from("1 candidate"),
+            count => format!("{} candidates", count),
+        };
+        if candidates.len() <= MAX_LISTED {
+            for &address in candidates {
+                text += &format!("\n0x{:03X} = {}", address, memory[address as usize]);
+            }
+        }
+
+        text
+    }
+}
+
+// Lines typed on stdin, read on their own thread so the emulator doesn't wait
+pub fn stdin_lines() -> Receiver<String> {
+    let (sender, lines) = channel();
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => if sender.send(line).is_err() { break },
+                Err(_) => break,
+            }
+        }
+    });
+
+    lines
+}
+
+// Hex like 0x2F0, or decimal
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+
+#[test]
+fn test_parse_cheats() {
+    let cheats = Cheats::parse("# Tetris\nfreeze 0x2F0 3 Infinite lives\n\npoke 768 0x10  # level\n").unwrap();
+
+    assert_eq!(cheats.cheats(), &[
+        Cheat { kind: Kind::Freeze, address: 0x2F0, value: 3, name: String::from("Infinite lives") },
+        Cheat { kind: Kind::Poke, address: 0x300, value: 0x10, name: String::new() },
+    ]);
+    assert_eq!(Cheats::parse(&cheats.to_text()), Ok(cheats));
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(Cheats::parse("freeze 0x2F0").is_err());
+    assert!(Cheats::parse("freeze 0x1000 1").is_err());
+    assert!(Cheats::parse("poke 0x200 256").is_err());
+    assert_eq!(Cheats::parse("poke 1 2\nfrozen 1 2"), Err(String::from("line 2: expected poke or freeze: frozen 1 2")));
+}
+
+#[test]
+fn test_to_line() {
+    let cheat = Cheat { kind: Kind::Freeze, address: 0x2F0, value: 3, name: String::from("Lives") };
+
+    assert_eq!(cheat.to_line(), "freeze 0x2F0 0x03 Lives");
+}
+
+#[test]
+fn test_add_replaces() {
+    let mut cheats = Cheats::new();
+    cheats.add(Cheat::parse("poke 0x300 1").unwrap());
+    cheats.add(Cheat::parse("freeze 0x300 2").unwrap());
+
+    assert_eq!(cheats.cheats().len(), 1);
+    assert_eq!(cheats.cheats()[0].value, 2);
+    assert!(cheats.remove(0x300));
+    assert!(!cheats.remove(0x300));
+}
+
+#[test]
+fn test_search() {
+    let mut memory = [0; 4096];
+    memory[0x300] = 3;
+    memory[0x301] = 3;
+    let mut search = Search::new(&memory);
+
+    // Lose a life
+    memory[0x300] = 2;
+    memory[0x302] = 5;
+    search.narrow(&memory, Compare::Decreased);
+    assert_eq!(search.candidates(), &[0x300]);
+
+    search.narrow(&memory, Compare::Unchanged);
+    assert_eq!(search.candidates(), &[0x300]);
+    search.narrow(&memory, Compare::Equal(1));
+    assert!(search.candidates().is_empty());
+}
+
+#[test]
+fn test_console() {
+    let mut emu = Chip8::new();
+    emu.poke(0x300, 3);
+    let mut console = Console::new(Cheats::new(), PathBuf::from("cheats.txt"));
+
+    assert!(console.execute("eq 3", &mut emu).is_err());
+    assert_eq!(console.execute("new", &mut emu), Ok(String::from("4096 candidates")));
+
+    emu.poke(0x300, 2);
+    emu.poke(0x400, 2);
+    assert_eq!(console.execute("eq 2", &mut emu).unwrap().lines().count(), 3);
+
+    emu.poke(0x300, 1);
+    assert_eq!(console.execute("dec", &mut emu), Ok(String::from("1 candidate\n0x300 = 1")));
+
+    console.execute("freeze 0x300 9 Lives", &mut emu).unwrap();
+    assert_eq!(emu.memory()[0x300], 9);
+
+    emu.poke(0x300, 0);
+    console.cheats().freeze(&mut emu);
+    assert_eq!(emu.memory()[0x300], 9);
+
+    assert!(console.execute("frobnicate", &mut emu).is_err());
+}
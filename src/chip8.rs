@@ -1,17 +1,16 @@
 use rand::prelude::*;
 
 use crate::opcode::Opcode;
+use crate::coverage::Coverage;
+use crate::profiler::Profiler;
 use crate::util::byte_to_bits;
-use crate::graphics::Screen;
+use crate::quirks::Quirks;
+use crate::rom::{self, DEFAULT_LOAD_ADDRESS};
+use crate::timing::{Timing, vip_cycles, MAX_IPS, VIP_CYCLES_PER_FRAME, VIP_INTERRUPT_CYCLES};
 
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::fs::File;
-
-use sdl2::EventPump;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
 
+// The font sprites sit at the start of memory
+pub const FONT_SIZE: usize = 16 * 5;
 
 static SPRITES: [[u8; 5]; 16] = [
     [0xF0, 0x90, 0x90, 0x90, 0xF0],
@@ -34,20 +33,30 @@ static SPRITES: [[u8; 5]; 16] = [
 
 #[allow(non_snake_case)]
 pub struct Chip8 {
-    memory: [u8; 4096],
-    stack: [u16; 16],
-    V: [u8; 16], // CPU registers
-    I: u16, // Index register
-    pc: u16, // Program counter
-    sp: u8, // Stack pointer
-    opcode: Opcode,
-    delay_timer: u8,
-    sound_timer: u8,
+    pub(crate) memory: [u8; 4096],
+    pub(crate) stack: [u16; 16],
+    pub(crate) V: [u8; 16], // CPU registers
+    pub(crate) I: u16, // Index register
+    pub(crate) pc: u16, // Program counter
+    pub(crate) sp: u8, // Stack pointer
+    pub(crate) opcode: Opcode,
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
     rng: ThreadRng,
-    screen: Screen,
-    draw_flag: bool,
-    event_pump: EventPump,
-    keyboard: [bool; 16],
+    pub(crate) screen: [[u8; 64]; 32],
+    pub(crate) draw_flag: bool,
+    pub(crate) keyboard: [bool; 16],
+    key_wait: Option<[bool; 16]>, // Keys held down when LD_K started waiting
+    timing: Timing,
+    cycles: i32, // Cycles, or 60ths of an instruction, left over from the previous frame
+    quirks: Quirks,
+    pub(crate) vblank_wait: bool, // CPU is halted until the next frame
+    rom: Vec<u8>, // Kept for hard resets
+    rom_sha1: String,
+    load_address: u16, // Where ROMs are loaded and run from
+    instructions: u64, // Executed since the emulator started
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
 }
 
 
@@ -55,8 +64,6 @@ impl Chip8 {
     pub fn new() -> Chip8 {
         println!("Initializing emulator.");
 
-        let sdl_context = sdl2::init().unwrap();
-
         Chip8 {
             memory: Chip8::init_memory(),
             stack: [0; 16],
@@ -68,10 +75,20 @@ impl Chip8 {
             delay_timer: 0,
             sound_timer: 0,
             rng: rand::thread_rng(),
-            screen: Screen::new(&sdl_context),
+            screen: [[0; 64]; 32],
             draw_flag: false,
-            event_pump: sdl_context.event_pump().unwrap(),
             keyboard: [false; 16],
+            key_wait: None,
+            timing: Timing::Fixed(1),
+            cycles: 0,
+            quirks: Quirks::default(),
+            vblank_wait: false,
+            rom: Vec::new(),
+            rom_sha1: String::new(),
+            load_address: DEFAULT_LOAD_ADDRESS,
+            instructions: 0,
+            profiler: None,
+            coverage: None,
         }
     }
 
@@ -90,112 +107,245 @@ impl Chip8 {
         memory
     }
 
-    pub fn load_game(&mut self, path: &str) {
+    // Load a ROM from a file, see rom::read for what paths can be
+    pub fn load_game(&mut self, path: &str) -> Result<(), String> {
         println!("Loading ROM.");
 
-        let f = File::open(path).unwrap();
-        let reader = BufReader::new(f);
+        let rom = rom::read(path)?;
+        self.load_rom_bytes(&rom)?;
 
-        let mut index = 0;
-        for byte in reader.bytes() {
-            let byte = byte.unwrap();
-            self.memory[0x200+index] = byte;
+        println!("{} bytes loaded ({})", rom.len(), path);
+        Ok(())
+    }
 
-            index += 1;
+    // Load a ROM at the load address into freshly cleared memory and start
+    // running it from there
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), String> {
+        let start = self.load_address as usize;
+        let room = match self.memory.len().checked_sub(start) {
+            Some(room) if room >= 2 => room,
+            _ => return Err(format!("load address {:#05X} is outside memory", start)),
+        };
+        if rom.len() > room {
+            return Err(format!("ROM is {} bytes, only {} fit at {:#05X}", rom.len(), room, start));
         }
 
-        println!("{} bytes loaded ({})", index-1, path);
-    }
-
-    pub fn handle_events(&mut self) {
-        for event in self.event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), ..} => {
-                    println!("Shutting down!");
-                    std::process::exit(0);
-                },
-                Event::KeyDown { keycode: Some(key), ..} => {
-                    let key = match key {
-                        Keycode::Num1 => 0x0,
-                        Keycode::Num2 => 0x1,
-                        Keycode::Num3 => 0x2,
-                        Keycode::Num4 => 0x3,
-                        Keycode::Q => 0x4,
-                        Keycode::W => 0x5,
-                        Keycode::E => 0x6,
-                        Keycode::R => 0x7,
-                        Keycode::A => 0x8,
-                        Keycode::S => 0x9,
-                        Keycode::D => 0xA,
-                        Keycode::F => 0xB,
-                        Keycode::Z => 0xC,
-                        Keycode::X => 0xD,
-                        Keycode::C => 0xE,
-                        Keycode::V => 0xF,
-                        _ => {continue;}
-                    };
-
-                    self.keyboard[key] = true;
+        self.memory = Chip8::init_memory();
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        self.rom = rom.to_vec();
+        self.rom_sha1 = sha1_smol::Sha1::from(rom).digest().to_string();
+        self.soft_reset();
+
+        Ok(())
+    }
+
+    // Restart the program like the reset button would: the CPU, timers and
+    // screen are cleared but memory is left as the program left it
+    pub fn soft_reset(&mut self) {
+        self.stack = [0; 16];
+        self.V = [0; 16];
+        self.I = 0;
+        self.pc = self.load_address;
+        self.sp = 0;
+        self.opcode = Opcode::UNDEFINED;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.screen = [[0; 64]; 32];
+        self.draw_flag = true;
+        self.key_wait = None;
+        self.cycles = 0;
+        self.vblank_wait = false;
+    }
+
+    // Restart from scratch, with memory cleared and the ROM loaded again
+    pub fn hard_reset(&mut self) -> Result<(), String> {
+        let rom = std::mem::take(&mut self.rom);
+        self.load_rom_bytes(&rom)
+    }
+
+    // Where the next ROM is loaded, 0x200 unless a platform like the
+    // ETI-660 needs something else
+    pub fn set_load_address(&mut self, address: u16) {
+        self.load_address = address;
+    }
+
+    // SHA-1 of the loaded ROM as lowercase hex, used to look it up in the
+    // ROM database
+    pub fn rom_sha1(&self) -> &str {
+        &self.rom_sha1
+    }
+
+    pub fn framebuffer(&self) -> &[[u8; 64]; 32] {
+        &self.screen
+    }
+
+    pub fn memory(&self) -> &[u8; 4096] {
+        &self.memory
+    }
+
+    // Change a byte of memory, e.g. from the memory viewer
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.memory[address as usize & 0xFFF] = value;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn index(&self) -> u16 {
+        self.I
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut [bool; 16] {
+        &mut self.keyboard
+    }
+
+    // Whether the framebuffer changed since the last call
+    pub fn take_draw_flag(&mut self) -> bool {
+        std::mem::replace(&mut self.draw_flag, false)
+    }
+
+    // How many instructions have run in total, for measuring speed
+    pub fn instruction_count(&self) -> u64 {
+        self.instructions
+    }
+
+    // Start counting where time is spent, from the program's entry point
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new(self.load_address));
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // Start recording which code runs and which data is read
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    // The ROM as loaded, and where
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    pub fn sound_playing(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.cycles = 0;
+    }
+
+    // Run one 60Hz frame's worth of instructions
+    pub fn frame(&mut self) {
+        self.vblank_wait = false;
+
+        match self.timing {
+            Timing::Fixed(n) => {
+                for _ in 0..n {
+                    if self.vblank_wait {
+                        break;
+                    }
+
+                    self.step();
+                }
+            }
+            Timing::PerSecond(n) => {
+                self.cycles += n.min(MAX_IPS) as i32;
+
+                while self.cycles >= 60 && !self.vblank_wait {
+                    self.step();
+                    self.cycles -= 60;
+                }
+
+                // Instructions lost to the wait aren't made up for later
+                if self.vblank_wait {
+                    self.cycles %= 60;
+                }
+            }
+            Timing::Vip => {
+                self.cycles += (VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES) as i32;
+
+                // An instruction that overruns the budget borrows from the next frame
+                while self.cycles > 0 && !self.vblank_wait {
+                    self.cycles -= self.step() as i32;
                 }
-                Event::KeyUp { keycode: Some(key), ..} => {
-                    let key = match key {
-                        Keycode::Num1 => 0x0,
-                        Keycode::Num2 => 0x1,
-                        Keycode::Num3 => 0x2,
-                        Keycode::Num4 => 0x3,
-                        Keycode::Q => 0x4,
-                        Keycode::W => 0x5,
-                        Keycode::E => 0x6,
-                        Keycode::R => 0x7,
-                        Keycode::A => 0x8,
-                        Keycode::S => 0x9,
-                        Keycode::D => 0xA,
-                        Keycode::F => 0xB,
-                        Keycode::Z => 0xC,
-                        Keycode::X => 0xD,
-                        Keycode::C => 0xE,
-                        Keycode::V => 0xF,
-                        _ => {continue;}
-                    };
-
-                    self.keyboard[key] = false;
+
+                // Whatever was left is spent waiting for the interrupt
+                if self.vblank_wait {
+                    self.cycles = 0;
                 }
-                _ => {}
             }
         }
+
+        self.update_timers();
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.end_frame();
+        }
     }
 
     pub fn tick(&mut self) {
-        // Fetch Opcode
-        let upper =  self.memory[self.pc as usize] as u16;
-        let lower = self.memory[self.pc as usize + 1] as u16;
+        self.step();
+
+        self.update_timers();
+    }
+
+    // Run one instruction, returning what it costs on the VIP
+    fn step(&mut self) -> u32 {
+        self.fetch_opcode();
+        let cycles = vip_cycles(&self.opcode, &self.V);
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record(self.pc, self.opcode, cycles);
+        }
+        let pc = self.pc;
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.record(pc, self.opcode, self.I);
+        }
+
+        self.execute_opcode();
+
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.record_branch(pc, self.opcode, self.pc);
+        }
+
+        cycles
+    }
+
+    fn fetch_opcode(&mut self) {
+        // Addresses wrap around at the end of memory, for pc like for I
+        let upper = self.memory[self.pc as usize & 0xFFF] as u16;
+        let lower = self.memory[(self.pc as usize + 1) & 0xFFF] as u16;
         let bytes = upper << 8 | lower;
 
         self.opcode = Opcode::from_bytes(bytes);
-        
-        // Execute Opcode
-        self.execute_opcode();
+        self.instructions += 1;
+    }
 
-        // Update timers
+    fn update_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer-=1;
         }
 
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                println!("BEEP!");
-            }
             self.sound_timer -= 1;
         }
-
-        if self.draw_flag {
-            self.screen.draw();
-            self.draw_flag = false;
-        }
     }
 
-    fn execute_opcode(&mut self) {
+    pub(crate) fn execute_opcode(&mut self) {
         self.pc += 2;
 
         match self.opcode {
@@ -270,30 +420,38 @@ impl Chip8 {
             }
             Opcode::ADD_V(x, y) => {
                 // Set Vx = Vx + Vy, set VF = carry
-                let sum = self.V[x] as u16 + self.V[y] as u16;
-                self.V[0xF] = if sum > 255 { 1 } else { 0 };
+                let (sum, carry) = self.V[x].overflowing_add(self.V[y]);
 
-                self.V[x] = sum as u8;
+                self.V[x] = sum;
+                self.V[0xF] = carry as u8;
             }
             Opcode::SUB(x, y) => {
-                self.V[0xF] = if self.V[x] > self.V[y] { 1 } else { 0 };
+                // Set Vx = Vx - Vy, set VF = NOT borrow
+                let (diff, borrow) = self.V[x].overflowing_sub(self.V[y]);
 
-                self.V[x] = (self.V[x] as i8 - self.V[y] as i8) as u8;
+                self.V[x] = diff;
+                self.V[0xF] = !borrow as u8;
             }
             Opcode::SHR(x, _y) => {
                 // Set Vx = Vx SHR 1
-                self.V[0xF] = self.V[x] & 0b0001;
-                self.V[x] = self.V[x] >> 1;
+                let lost = self.V[x] & 0b0001;
+
+                self.V[x] >>= 1;
+                self.V[0xF] = lost;
             }
             Opcode::SUBN(x, y) => {
                 // Set Vx = Vy - Vx, set VF = NOT borrow
-                self.V[0xF] = if self.V[y] > self.V[x] { 1 } else { 0 };
-                self.V[x] = (self.V[y] as i8 - self.V[x] as i8) as u8;
+                let (diff, borrow) = self.V[y].overflowing_sub(self.V[x]);
+
+                self.V[x] = diff;
+                self.V[0xF] = !borrow as u8;
             }
             Opcode::SHL(x, _y) => {
                 // Set Vx = Vx SHL 1
-                self.V[0xF] = (self.V[x] & 0b10000000) >> 7;
-                self.V[x] = self.V[x] << 1;
+                let lost = (self.V[x] & 0b10000000) >> 7;
+
+                self.V[x] <<= 1;
+                self.V[0xF] = lost;
             }
             Opcode::SNE_V(x, y) => {
                 // Skip next instruction if Vx != Vy
@@ -316,14 +474,28 @@ impl Chip8 {
             }
             Opcode::DRW(x, y, n) => {
                 // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+                // Latch the coordinates first, x or y may be VF itself.
+                // The starting position always wraps, the rest of the sprite
+                // either wraps or is clipped at the edge.
+                let vx = self.V[x] as usize % 64;
+                let vy = self.V[y] as usize % 32;
+
                 self.V[0xF] = 0;
                 for row in 0..n as u16 {
-                    let byte = self.memory[(row+self.I) as usize];
+                    let byte = self.memory[(self.I as usize + row as usize) & 0xFFF];
                     let bits = byte_to_bits(byte);
-                    let screen_y = ((self.V[y] as u16 +row) as usize) % 32;
+                    let screen_y = vy + row as usize;
+                    if screen_y >= 32 && self.quirks.clip_y {
+                        break;
+                    }
+                    let screen_y = screen_y % 32;
 
                     for col in 0..8 {
-                        let screen_x = (self.V[x] as usize + col) % 64;
+                        let screen_x = vx + col;
+                        if screen_x >= 64 && self.quirks.clip_x {
+                            break;
+                        }
+                        let screen_x = screen_x % 64;
                         if self.screen[screen_y][screen_x] & bits[col] == 1 {
                             self.V[0xF] = 1
                         }
@@ -333,6 +505,7 @@ impl Chip8 {
                 }
 
                 self.draw_flag = true;
+                self.vblank_wait = self.quirks.display_wait;
             }
             Opcode::SKP(x) => {
                 // Skip next instruction if key with the value of Vx is pressed
@@ -351,30 +524,33 @@ impl Chip8 {
                 self.V[x] = self.delay_timer;
             }
             Opcode::LD_K(x) => {
-                // Wait for a key press, store the value of the key in Vx
-                let mut initial= self.keyboard;
-                let mut keypressed = false;
-
-                loop {
-                    self.handle_events();
-
-                    for i in 0..16 {
-                        if initial[i] == true && self.keyboard[i] == false {
-                            initial[i] = self.keyboard[i];
-                        } else if initial[i] == false && self.keyboard[i] == true {
-                            keypressed = true;
-                            self.V[x] = i as u8;
-                            break;
-                        }
+                // Wait for a key press, store the value of the key in Vx.
+                // Keys already held when the wait started have to be
+                // released and pressed again to count.
+                let mut held = self.key_wait.unwrap_or(self.keyboard);
+                let mut pressed = None;
+
+                for (key, down) in self.keyboard.iter().enumerate() {
+                    if !down {
+                        held[key] = false;
+                    } else if !held[key] {
+                        pressed = Some(key);
+                        break;
                     }
+                }
 
-                    if keypressed {
-                        break;
-                    } else {
-                        std::thread::sleep(std::time::Duration::from_secs_f64(1.0/60.0));
+                match pressed {
+                    Some(key) => {
+                        self.V[x] = key as u8;
+                        self.key_wait = None;
+                    }
+                    None => {
+                        // Run this instruction again next frame
+                        self.key_wait = Some(held);
+                        self.pc -= 2;
+                        self.vblank_wait = true;
                     }
                 }
-                
             }
             Opcode::LD_DT_V(x) => {
                 // Set delay timer = Vx
@@ -404,21 +580,21 @@ impl Chip8 {
 
                 let hundreds = num;
 
-                self.memory[self.I as usize] = hundreds;
-                self.memory[self.I as usize +1] = tens;
-                self.memory[self.I as usize +2] = ones;
+                self.memory[self.I as usize & 0xFFF] = hundreds;
+                self.memory[(self.I as usize + 1) & 0xFFF] = tens;
+                self.memory[(self.I as usize + 2) & 0xFFF] = ones;
 
             }
             Opcode::LD_I_V(x) => {
                 // Store registers V0 though Vx in memory starting at location I
                 for i in 0..x+1 {
-                    self.memory[self.I as usize+i] = self.V[i];
+                    self.memory[(self.I as usize + i) & 0xFFF] = self.V[i];
                 }
             }
             Opcode::LD_V_I(x) => {
                 // Read registers V0 through Vx from memory starting at location I
                 for i in 0..x+1 {
-                    self.V[i] = self.memory[self.I as usize+i];
+                    self.V[i] = self.memory[(self.I as usize + i) & 0xFFF];
                 }
             }
             Opcode::UNDEFINED => {
@@ -429,6 +605,87 @@ impl Chip8 {
 }
 
 
+#[test]
+fn test_load_rom_bytes() {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom_bytes(&[0x12, 0x34, 0x56]).unwrap();
+
+    assert_eq!(chip8.memory[0x200..0x203], [0x12, 0x34, 0x56]);
+    assert_eq!(chip8.pc, 0x200);
+    assert_eq!(chip8.rom_sha1(), "d54093641b8ab5efe6d9c8dca9fb39112cb1722a");
+}
+
+#[test]
+fn test_soft_reset() {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom_bytes(&[0x60, 0x05]).unwrap();
+    chip8.tick();
+    chip8.memory[0x300] = 0xAB;
+    chip8.delay_timer = 10;
+    chip8.screen[0][0] = 1;
+
+    chip8.soft_reset();
+
+    assert_eq!(chip8.pc, 0x200);
+    assert_eq!(chip8.V[0], 0);
+    assert_eq!(chip8.delay_timer, 0);
+    assert_eq!(chip8.screen[0][0], 0);
+    assert!(chip8.take_draw_flag());
+    assert_eq!(chip8.memory[0x300], 0xAB);
+}
+
+#[test]
+fn test_hard_reset() {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom_bytes(&[0x60, 0x05]).unwrap();
+    chip8.tick();
+    chip8.memory[0x200] = 0xFF;
+    chip8.memory[0x300] = 0xAB;
+
+    chip8.hard_reset().unwrap();
+
+    assert_eq!(chip8.pc, 0x200);
+    assert_eq!(chip8.V[0], 0);
+    assert_eq!(chip8.memory[0x200..0x202], [0x60, 0x05]);
+    assert_eq!(chip8.memory[0x300], 0);
+    assert_eq!(chip8.memory[0..5], SPRITES[0]);
+}
+
+#[test]
+fn test_load_address() {
+    let mut chip8 = Chip8::new();
+    chip8.set_load_address(rom::ETI_660_LOAD_ADDRESS);
+    chip8.load_rom_bytes(&[0x16, 0x00]).unwrap();
+
+    assert_eq!(chip8.memory[0x600..0x602], [0x16, 0x00]);
+    assert_eq!(chip8.memory[0x200], 0);
+    assert_eq!(chip8.pc, 0x600);
+
+    // Fills memory up to the end, but no further
+    assert!(chip8.load_rom_bytes(&[0; 4096 - 0x600]).is_ok());
+    assert!(chip8.load_rom_bytes(&[0; 4096 - 0x600 + 1]).is_err());
+
+    // Nothing fits past the end of memory, or where an instruction would
+    // run past it
+    chip8.set_load_address(0x1000);
+    assert!(chip8.load_rom_bytes(&[]).is_err());
+    chip8.set_load_address(0xFFF);
+    assert!(chip8.load_rom_bytes(&[0x12]).is_err());
+    chip8.set_load_address(0xFFFF);
+    assert!(chip8.load_rom_bytes(&[0x12, 0x00]).is_err());
+}
+
+#[test]
+fn test_highest_load_address() {
+    let mut chip8 = Chip8::new();
+    chip8.set_load_address(rom::MAX_LOAD_ADDRESS);
+    chip8.load_rom_bytes(&[0x1F, 0xFE]).unwrap(); // JP FFE
+    chip8.frame();
+
+    assert_eq!(chip8.pc, 0xFFE);
+    assert!(chip8.instruction_count() > 0);
+}
+
 #[allow(non_snake_case)]
 #[test]
 fn test_CLS() {
@@ -746,6 +1003,167 @@ fn test_SUBN_overflow() {
     assert_eq!(chip8.V[0xF], 0);
 }
 
+#[test]
+#[allow(non_snake_case)]
+fn test_SUB_equal() {
+    let mut chip8 = Chip8::new();
+    chip8.V[0] = 7;
+    chip8.V[1] = 7;
+
+    chip8.opcode = Opcode::SUB(0, 1);
+    chip8.execute_opcode();
+
+    assert_eq!(chip8.V[0], 0);
+    assert_eq!(chip8.V[0xF], 1);
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_ADD_V_exhaustive() {
+    let mut chip8 = Chip8::new();
+
+    for a in 0..=255u8 {
+        for b in 0..=255u8 {
+            chip8.pc = 0x200;
+            chip8.V[0] = a;
+            chip8.V[1] = b;
+            chip8.opcode = Opcode::ADD_V(0, 1);
+            chip8.execute_opcode();
+
+            assert_eq!(chip8.V[0], a.wrapping_add(b));
+            assert_eq!(chip8.V[0xF], (a as u16 + b as u16 > 255) as u8);
+        }
+    }
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_SUB_exhaustive() {
+    let mut chip8 = Chip8::new();
+
+    for a in 0..=255u8 {
+        for b in 0..=255u8 {
+            chip8.pc = 0x200;
+            chip8.V[0] = a;
+            chip8.V[1] = b;
+            chip8.opcode = Opcode::SUB(0, 1);
+            chip8.execute_opcode();
+
+            assert_eq!(chip8.V[0], a.wrapping_sub(b));
+            assert_eq!(chip8.V[0xF], (a >= b) as u8);
+        }
+    }
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_SUBN_exhaustive() {
+    let mut chip8 = Chip8::new();
+
+    for a in 0..=255u8 {
+        for b in 0..=255u8 {
+            chip8.pc = 0x200;
+            chip8.V[0] = a;
+            chip8.V[1] = b;
+            chip8.opcode = Opcode::SUBN(0, 1);
+            chip8.execute_opcode();
+
+            assert_eq!(chip8.V[0], b.wrapping_sub(a));
+            assert_eq!(chip8.V[0xF], (b >= a) as u8);
+        }
+    }
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_OR_AND_XOR_exhaustive() {
+    let mut chip8 = Chip8::new();
+    let opcodes: [fn(usize, usize) -> Opcode; 3] = [Opcode::OR, Opcode::AND, Opcode::XOR];
+
+    for opcode in opcodes.iter() {
+        for a in 0..=255u8 {
+            for b in 0..=255u8 {
+                let result = match opcode(0, 1) {
+                    Opcode::OR(..) => a | b,
+                    Opcode::AND(..) => a & b,
+                    _ => a ^ b,
+                };
+
+                // VF is left alone
+                chip8.pc = 0x200;
+                chip8.V[0] = a;
+                chip8.V[1] = b;
+                chip8.V[0xF] = 0xAA;
+                chip8.opcode = opcode(0, 1);
+                chip8.execute_opcode();
+
+                assert_eq!(chip8.V[0], result);
+                assert_eq!(chip8.V[0xF], 0xAA);
+
+                // Unless it's the destination, then it gets the result
+                chip8.V[0xF] = a;
+                chip8.opcode = opcode(0xF, 1);
+                chip8.execute_opcode();
+
+                assert_eq!(chip8.V[0xF], result);
+            }
+        }
+    }
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_SHR_SHL_exhaustive() {
+    let mut chip8 = Chip8::new();
+
+    for a in 0..=255u8 {
+        chip8.V[0] = a;
+        chip8.opcode = Opcode::SHR(0, 1);
+        chip8.execute_opcode();
+
+        assert_eq!(chip8.V[0], a >> 1);
+        assert_eq!(chip8.V[0xF], a & 1);
+
+        chip8.V[0] = a;
+        chip8.opcode = Opcode::SHL(0, 1);
+        chip8.execute_opcode();
+
+        assert_eq!(chip8.V[0], a << 1);
+        assert_eq!(chip8.V[0xF], a >> 7);
+    }
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_ALU_VF_written_last() {
+    let mut chip8 = Chip8::new();
+
+    // With VF as the destination the flag must win over the result
+    let cases = [
+        (Opcode::ADD_V(0xF, 0), 0xFF, 0x01, 1),
+        (Opcode::ADD_V(0xF, 0), 0x01, 0x01, 0),
+        (Opcode::SUB(0xF, 0), 0x80, 0x01, 1),
+        (Opcode::SUB(0xF, 0), 0x01, 0x80, 0),
+        (Opcode::SUBN(0xF, 0), 0x01, 0x80, 1),
+        (Opcode::SUBN(0xF, 0), 0x80, 0x01, 0),
+        (Opcode::SHR(0xF, 0), 0x03, 0x00, 1),
+        (Opcode::SHL(0xF, 0), 0x40, 0x00, 0),
+        // The logical ops set no flag, VF just gets the result
+        (Opcode::OR(0xF, 0), 0x0C, 0x03, 0x0F),
+        (Opcode::AND(0xF, 0), 0x0C, 0x06, 0x04),
+        (Opcode::XOR(0xF, 0), 0x0C, 0x06, 0x0A),
+    ];
+
+    for (opcode, vf, v0, flag) in cases.iter() {
+        chip8.V[0xF] = *vf;
+        chip8.V[0] = *v0;
+        chip8.opcode = *opcode;
+        chip8.execute_opcode();
+
+        assert_eq!(chip8.V[0xF], *flag, "{:?}", opcode);
+    }
+}
+
 #[test]
 #[allow(non_snake_case)]
 fn test_SHL() {
@@ -864,10 +1282,192 @@ fn test_DRW() {
     chip8.V[1] = 26;
     chip8.opcode = Opcode::DRW(0, 1, 6);
     chip8.execute_opcode();
-    
-    chip8.screen.draw();
 
-    std::thread::sleep(std::time::Duration::from_secs_f32(3.0));
+    // A plus sign in each corner
+    for &(x, y) in [(0, 0), (56, 0), (0, 26), (56, 26)].iter() {
+        assert_eq!(chip8.screen[y][x..x+8], [0, 0, 0, 1, 1, 0, 0, 0]);
+        assert_eq!(chip8.screen[y+2][x..x+8], [1, 1, 1, 1, 1, 1, 1, 1]);
+        assert_eq!(chip8.screen[y+5][x..x+8], [0, 0, 0, 1, 1, 0, 0, 0]);
+    }
+
+    assert_eq!(chip8.V[0xF], 0);
+    assert!(chip8.draw_flag);
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_DRW_display_wait() {
+    let mut chip8 = Chip8::new();
+    let mut quirks = Quirks::modern();
+    quirks.display_wait = true;
+    chip8.set_quirks(quirks);
+    chip8.set_timing(Timing::Fixed(10));
+
+    // 0x200: LD V1, 0 / DRW V0, V0, 1 / ADD V1, 1 / JP 0x202
+    let program = [0x61, 0x00, 0xD0, 0x01, 0x71, 0x01, 0x12, 0x02];
+    chip8.memory[0x200..0x200 + program.len()].copy_from_slice(&program);
+
+    chip8.frame();
+
+    // The frame stops right after the draw
+    assert_eq!(chip8.pc, 0x204);
+    assert_eq!(chip8.V[1], 0);
+
+    chip8.frame();
+
+    assert_eq!(chip8.pc, 0x204);
+    assert_eq!(chip8.V[1], 1);
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_DRW_no_display_wait() {
+    let mut chip8 = Chip8::new();
+    let mut quirks = Quirks::modern();
+    quirks.display_wait = false;
+    chip8.set_quirks(quirks);
+    chip8.set_timing(Timing::Fixed(10));
+
+    let program = [0x61, 0x00, 0xD0, 0x01, 0x71, 0x01, 0x12, 0x02];
+    chip8.memory[0x200..0x200 + program.len()].copy_from_slice(&program);
+
+    chip8.frame();
+
+    // 10 instructions: three times around the loop after the LD
+    assert_eq!(chip8.pc, 0x202);
+    assert_eq!(chip8.V[1], 3);
+}
+
+#[test]
+fn test_instructions_per_second() {
+    let mut chip8 = Chip8::new();
+    chip8.set_timing(Timing::PerSecond(90));
+    // JP 0x200
+    chip8.memory[0x200..0x202].copy_from_slice(&[0x12, 0x00]);
+
+    // One and a half instructions a frame
+    chip8.frame();
+    assert_eq!(chip8.instruction_count(), 1);
+    chip8.frame();
+    assert_eq!(chip8.instruction_count(), 3);
+    chip8.frame();
+    assert_eq!(chip8.instruction_count(), 4);
+
+    // Far too many runs as many as the most allowed
+    chip8.set_timing(Timing::PerSecond(u32::MAX));
+    chip8.frame();
+    assert_eq!(chip8.instruction_count(), 4 + MAX_IPS as u64 / 60);
+}
+
+// Draw a 2x2 block with its top left corner at (x, y)
+#[cfg(test)]
+fn draw_block(clip_x: bool, clip_y: bool, x: u8, y: u8) -> Chip8 {
+    let mut chip8 = Chip8::new();
+    let mut quirks = Quirks::modern();
+    quirks.clip_x = clip_x;
+    quirks.clip_y = clip_y;
+    chip8.set_quirks(quirks);
+
+    chip8.memory[0x300] = 0b11000000;
+    chip8.memory[0x301] = 0b11000000;
+    chip8.I = 0x300;
+
+    chip8.V[0] = x;
+    chip8.V[1] = y;
+    chip8.opcode = Opcode::DRW(0, 1, 2);
+    chip8.execute_opcode();
+
+    chip8
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_DRW_wrap_right_edge() {
+    let chip8 = draw_block(false, false, 63, 10);
+
+    assert_eq!(chip8.screen[10][63], 1);
+    assert_eq!(chip8.screen[10][0], 1);
+    assert_eq!(chip8.screen[11][63], 1);
+    assert_eq!(chip8.screen[11][0], 1);
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_DRW_clip_right_edge() {
+    let chip8 = draw_block(true, false, 63, 10);
+
+    assert_eq!(chip8.screen[10][63], 1);
+    assert_eq!(chip8.screen[10][0], 0);
+    assert_eq!(chip8.screen[11][63], 1);
+    assert_eq!(chip8.screen[11][0], 0);
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_DRW_wrap_bottom_edge() {
+    let chip8 = draw_block(false, false, 10, 31);
+
+    assert_eq!(chip8.screen[31][10], 1);
+    assert_eq!(chip8.screen[31][11], 1);
+    assert_eq!(chip8.screen[0][10], 1);
+    assert_eq!(chip8.screen[0][11], 1);
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_DRW_clip_bottom_edge() {
+    let chip8 = draw_block(false, true, 10, 31);
+
+    assert_eq!(chip8.screen[31][10], 1);
+    assert_eq!(chip8.screen[31][11], 1);
+    assert_eq!(chip8.screen[0][10], 0);
+    assert_eq!(chip8.screen[0][11], 0);
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_DRW_corner_per_axis() {
+    // Clipping only x still wraps the bottom row around to the top
+    let chip8 = draw_block(true, false, 63, 31);
+    assert_eq!(chip8.screen[31][63], 1);
+    assert_eq!(chip8.screen[0][63], 1);
+    assert_eq!(chip8.screen[31][0], 0);
+    assert_eq!(chip8.screen[0][0], 0);
+
+    // And clipping only y still wraps the right column around to the left
+    let chip8 = draw_block(false, true, 63, 31);
+    assert_eq!(chip8.screen[31][63], 1);
+    assert_eq!(chip8.screen[31][0], 1);
+    assert_eq!(chip8.screen[0][63], 0);
+    assert_eq!(chip8.screen[0][0], 0);
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_DRW_start_always_wraps() {
+    // A start position off the screen wraps even when clipping
+    let chip8 = draw_block(true, true, 64 + 5, 32 + 5);
+
+    assert_eq!(chip8.screen[5][5], 1);
+    assert_eq!(chip8.screen[6][6], 1);
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_DRW_VF_coordinate() {
+    let mut chip8 = Chip8::new();
+    chip8.memory[0x300] = 0b10000000;
+    chip8.I = 0x300;
+
+    // Drawn at x = 10, before VF is cleared for the collision flag
+    chip8.V[0xF] = 10;
+    chip8.V[0] = 5;
+    chip8.opcode = Opcode::DRW(0xF, 0, 1);
+    chip8.execute_opcode();
+
+    assert_eq!(chip8.screen[5][10], 1);
+    assert_eq!(chip8.screen[5][0], 0);
+    assert_eq!(chip8.V[0xF], 0);
 }
 
 #[test]
@@ -882,6 +1482,47 @@ fn test_LD_V_DT() {
     assert_eq!(chip8.V[0], 12);
 }
 
+#[test]
+#[allow(non_snake_case)]
+fn test_LD_K_waits() {
+    let mut chip8 = Chip8::new();
+
+    chip8.opcode = Opcode::LD_K(0);
+    chip8.execute_opcode();
+
+    // No key yet, the instruction repeats
+    assert_eq!(chip8.pc, 0x200);
+    assert!(chip8.vblank_wait);
+
+    chip8.keyboard[0xA] = true;
+    chip8.execute_opcode();
+
+    assert_eq!(chip8.pc, 0x202);
+    assert_eq!(chip8.V[0], 0xA);
+}
+
+#[test]
+#[allow(non_snake_case)]
+fn test_LD_K_ignores_held_key() {
+    let mut chip8 = Chip8::new();
+    chip8.keyboard[0x5] = true;
+
+    chip8.opcode = Opcode::LD_K(0);
+    chip8.execute_opcode();
+    chip8.execute_opcode();
+
+    // Still held from before the wait
+    assert_eq!(chip8.pc, 0x200);
+
+    chip8.keyboard[0x5] = false;
+    chip8.execute_opcode();
+    chip8.keyboard[0x5] = true;
+    chip8.execute_opcode();
+
+    assert_eq!(chip8.pc, 0x202);
+    assert_eq!(chip8.V[0], 0x5);
+}
+
 #[test]
 #[allow(non_snake_case)]
 fn test_LD_DT_V() {
@@ -991,4 +1632,76 @@ fn test_LD_V_I() {
     chip8.execute_opcode();
 
     assert_eq!(chip8.V, vals);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_poke() {
+    let mut chip8 = Chip8::new();
+    chip8.poke(0x300, 0xAB);
+    chip8.poke(0x1300, 0xCD);
+
+    assert_eq!(chip8.memory()[0x300], 0xCD);
+}
+
+#[test]
+fn test_memory_wraps() {
+    // I can go past the end of memory with ADD I, Vx, reads and writes
+    // from there wrap around to the start
+    let mut chip8 = Chip8::new();
+    chip8.I = 0xFFF;
+    chip8.V[0] = 123;
+    chip8.opcode = Opcode::LD_B(0);
+    chip8.execute_opcode();
+    assert_eq!([chip8.memory[0xFFF], chip8.memory[0x000], chip8.memory[0x001]], [1, 2, 3]);
+
+    chip8.I = 0xFFFF;
+    chip8.V[..3].copy_from_slice(&[7, 8, 9]);
+    chip8.opcode = Opcode::LD_I_V(2);
+    chip8.execute_opcode();
+    assert_eq!([chip8.memory[0xFFF], chip8.memory[0x000], chip8.memory[0x001]], [7, 8, 9]);
+
+    chip8.V = [0; 16];
+    chip8.opcode = Opcode::LD_V_I(2);
+    chip8.execute_opcode();
+    assert_eq!(chip8.V[..3], [7, 8, 9]);
+
+    chip8.memory[0xFFF] = 0x80;
+    chip8.memory[0x000] = 0x40;
+    chip8.V = [0; 16];
+    chip8.opcode = Opcode::DRW(0, 1, 2);
+    chip8.execute_opcode();
+    assert_eq!(chip8.screen[0][0..2], [1, 0]);
+    assert_eq!(chip8.screen[1][0..2], [0, 1]);
+
+    // So does an instruction at the very end of memory
+    chip8.memory[0xFFF] = 0x10;
+    chip8.memory[0x000] = 0x00;
+    chip8.pc = 0xFFF;
+    chip8.step();
+    assert_eq!(chip8.pc, 0x000);
+}
+
+#[test]
+fn test_profiler() {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom_bytes(&[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE]).unwrap();
+    chip8.set_timing(Timing::Fixed(4));
+    chip8.enable_profiler();
+    chip8.frame();
+
+    let profiler = chip8.profiler().unwrap();
+    assert!(profiler.report(10).starts_with("1 frames, 4 instructions"));
+    assert!(profiler.folded().contains("sub_200;sub_204 "));
+}
+
+#[test]
+fn test_coverage() {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom_bytes(&[0x40, 0x00, 0x12, 0x00, 0x12, 0x04]).unwrap();
+    chip8.enable_coverage();
+    chip8.tick();
+    chip8.tick();
+
+    let coverage = chip8.coverage().unwrap();
+    assert!(coverage.summary(chip8.rom(), chip8.load_address()).starts_with("2 of 3 instructions run"));
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rom::{DEFAULT_LOAD_ADDRESS, MAX_LOAD_ADDRESS};
+use crate::timing::MAX_IPS;
+
+// Settings read from the config file, which command line options override.
+// Anything missing from the file keeps its default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    // `fixed` runs `ips` instructions per second, `vip` runs at the speed
+    // of the COSMAC VIP
+    pub timing: String,
+    pub ips: u32,
+    // A quirks profile, `vip` or `modern`
+    pub quirks: String,
+    // Where ROMs are loaded, 512 (0x200) for most, 1536 (0x600) for the
+    // ETI-660
+    pub load_address: u16,
+    // How many times normal speed fast forward runs, 0 for as fast as
+    // possible
+    pub fast_forward: f32,
+    // The fraction of normal speed slow motion runs at
+    pub slow_motion: f32,
+    // A palette preset or file
+    pub palette: String,
+    // How many window pixels each CHIP-8 pixel starts out as
+    pub scale: u32,
+    pub filter: String,
+    pub scaling: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    pub screenshot_dir: String,
+    pub screenshot_format: String,
+    pub recording_dir: String,
+    // Where cheats are saved, `chip8/cheats` next to this file by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cheat_dir: Option<String>,
+    pub audio: Audio,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Audio {
+    // Pitch of the buzzer in Hz
+    pub tone: f32,
+    // From 0 for silent to 1
+    pub volume: f32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            timing: String::from("fixed"),
+            ips: 60,
+            quirks: String::from("modern"),
+            load_address: DEFAULT_LOAD_ADDRESS,
+            fast_forward: 0.0,
+            slow_motion: 0.25,
+            palette: String::from("classic"),
+            scale: 20,
+            filter: String::from("none"),
+            scaling: String::from("integer"),
+            keymap: None,
+            database: None,
+            screenshot_dir: String::from("."),
+            screenshot_format: String::from("png"),
+            recording_dir: String::from("."),
+            cheat_dir: None,
+            audio: Audio::default(),
+        }
+    }
+}
+
+impl Default for Audio {
+    fn default() -> Audio {
+        Audio {
+            tone: 440.0,
+            volume: 0.25,
+        }
+    }
+}
+
+impl Config {
+    // `chip8/config.toml` in the XDG config directory, usually ~/.config
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("chip8").join("config.toml"))
+    }
+
+    // Load the config file, or the defaults if there isn't one
+    pub fn load() -> Result<Config, String> {
+        match Config::path() {
+            Some(path) if path.exists() => {
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| format!("could not read config {}: {}", path.display(), e))?;
+
+                Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        if config.load_address > MAX_LOAD_ADDRESS {
+            return Err(format!("load-address {:#X} is outside memory", config.load_address));
+        }
+        if config.ips > MAX_IPS {
+            return Err(format!("ips can be at most {}", MAX_IPS));
+        }
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+}
+
+
+#[test]
+fn test_parse_partial() {
+    let config = Config::parse("quirks = \"vip\"\n[audio]\nvolume = 0.5\n").unwrap();
+
+    assert_eq!(config.quirks, "vip");
+    assert_eq!(config.audio.volume, 0.5);
+    assert_eq!(config.audio.tone, Audio::default().tone);
+    assert_eq!(config.ips, Config::default().ips);
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(Config::parse("ips = \"fast\"").is_err());
+    assert!(Config::parse("colour = \"red\"").is_err());
+    assert!(Config::parse("load-address = 4096").is_err());
+    assert!(Config::parse("load-address = 4095").is_err());
+    assert!(Config::parse("load-address = 4094").is_ok());
+    assert!(Config::parse("ips = 4294967295").is_err());
+    assert!(Config::parse("ips = 1000000").is_ok());
+}
+
+#[test]
+fn test_round_trip() {
+    let config = Config {
+        keymap: Some(String::from("keys.txt")),
+        screenshot_dir: String::from("/tmp/shots"),
+        ..Config::default()
+    };
+
+    assert_eq!(Config::parse(&config.to_toml()), Ok(config));
+}
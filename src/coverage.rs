@@ -0,0 +1,279 @@
+// Which parts of a program a run exercised: the instructions executed, the
+// bytes read as data by DRW and LD Vx, [I], and which way skips went.
+
+use std::fmt::Write;
+
+use crate::analysis::Analysis;
+use crate::opcode::Opcode;
+
+pub struct Coverage {
+    executed: Vec<u64>,
+    read: Vec<bool>,
+    // How often each skip instruction skipped, and didn't
+    skips: Vec<(u64, u64)>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            executed: vec![0; 4096],
+            read: vec![false; 4096],
+            skips: vec![(0, 0); 4096],
+        }
+    }
+
+    // Called before each instruction runs
+    pub(crate) fn record(&mut self, pc: u16, opcode: Opcode, index: u16) {
+        self.executed[pc as usize & 0xFFF] += 1;
+
+        let length = match opcode {
+            Opcode::DRW(_, _, n) => n as u16,
+            Opcode::LD_V_I(x) => x as u16 + 1,
+            _ => 0,
+        };
+        // I wraps around the end of memory like it does when the sprite or
+        // registers are read
+        for offset in 0..length {
+            self.read[index.wrapping_add(offset) as usize & 0xFFF] = true;
+        }
+    }
+
+    // Called after each instruction ran, with where it left pc
+    pub(crate) fn record_branch(&mut self, pc: u16, opcode: Opcode, next_pc: u16) {
+        if is_skip(opcode) {
+            let skips = &mut self.skips[pc as usize & 0xFFF];
+            if next_pc == pc.wrapping_add(4) {
+                skips.0 += 1;
+            } else {
+                skips.1 += 1;
+            }
+        }
+    }
+
+    // Reached statically or seen running
+    fn instructions(&self, analysis: &Analysis, rom: &[u8], load_address: u16) -> Vec<(u16, Opcode)> {
+        let end = load_address as usize + rom.len();
+        let mut instructions = Vec::new();
+        let mut address = load_address as usize;
+
+        while address + 1 < end {
+            let offset = address - load_address as usize;
+            if self.executed[address] > 0 || analysis.disassemble(address as u16).is_some() {
+                let bytes = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+                instructions.push((address as u16, Opcode::from_bytes(bytes)));
+                address += 2;
+            } else {
+                address += 1;
+            }
+        }
+
+        instructions
+    }
+
+    fn is_code(&self, analysis: &Analysis, address: usize) -> bool {
+        analysis.is_code(address as u16) || self.executed[address] > 0 || self.executed[address.wrapping_sub(1) & 0xFFF] > 0
+    }
+
+    pub fn summary(&self, rom: &[u8], load_address: u16) -> String {
+        let analysis = Analysis::new(rom, load_address);
+        let instructions = self.instructions(&analysis, rom, load_address);
+
+        let run = instructions.iter().filter(|&&(address, _)| self.executed[address as usize] > 0).count();
+        let branches: Vec<(u64, u64)> = instructions.iter()
+            .filter(|&&(_, opcode)| is_skip(opcode))
+            .map(|&(address, _)| self.skips[address as usize])
+            .collect();
+        let branches_taken: usize = branches.iter().map(|&(skipped, not)| (skipped > 0) as usize + (not > 0) as usize).sum();
+        let data: Vec<usize> = (load_address as usize..load_address as usize + rom.len())
+            .filter(|&address| !self.is_code(&analysis, address))
+            .collect();
+        let read = data.iter().filter(|&&address| self.read[address]).count();
+
+        format!(
+            "{} of {} instructions run ({}), {} of {} skip branches taken ({}), {} of {} data bytes read ({})\n",
+            run, instructions.len(), percent(run, instructions.len()),
+            branches_taken, branches.len() * 2, percent(branches_taken, branches.len() * 2),
+            read, data.len(), percent(read, data.len()))
+    }
+
+    // The ROM disassembled like `chip8 analyze` does, with how often each
+    // instruction ran, or ##### if it never did, which way skips went and
+    // how much of the data was read
+    pub fn listing(&self, rom: &[u8], load_address: u16) -> String {
+        let analysis = Analysis::new(rom, load_address);
+        let mut text = self.summary(rom, load_address);
+        let instructions = self.instructions(&analysis, rom, load_address);
+        let starts: Vec<u16> = instructions.iter().map(|&(address, _)| address).collect();
+
+        let end = load_address as usize + rom.len();
+        let mut address = load_address as usize;
+        let mut next = instructions.iter().peekable();
+
+        while address < end {
+            let offset = address - load_address as usize;
+
+            match next.peek() {
+                Some(&&(start, opcode)) if start as usize == address => {
+                    next.next();
+                    if let Some(label) = analysis.label(start) {
+                        writeln!(text, "{}:", label).unwrap();
+                    }
+
+                    let count = match self.executed[address] {
+                        0 => String::from("#####"),
+                        count => count.to_string(),
+                    };
+                    let instruction = format!("0x{:03X}  {:02X}{:02X}  {}", address, rom[offset], rom[offset + 1], opcode);
+                    match self.skips[address] {
+                        _ if !is_skip(opcode) => writeln!(text, "{:>8}  {}", count, instruction),
+                        (skipped, not) => writeln!(text, "{:>8}  {:<32}  ; skipped {}, didn't {}", count, instruction, skipped, not),
+                    }.unwrap();
+                    address += 2;
+                }
+                _ => {
+                    // Up to 8 bytes of data a line, up to the next instruction
+                    let length = (address..end.min(address + 8))
+                        .take_while(|&address| !starts.contains(&(address as u16)))
+                        .count();
+                    let bytes: Vec<String> = rom[offset..offset + length].iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                    let read = (address..address + length).filter(|&address| self.read[address]).count();
+
+                    let data = format!("0x{:03X}  DB {}", address, bytes.join(", "));
+                    writeln!(text, "{:>8}  {:<32}  ; {} of {} read", "", data, read, length).unwrap();
+                    address += length;
+                }
+            }
+        }
+
+        text
+    }
+
+    // An lcov tracefile for the ROM, with addresses for line numbers, skips
+    // as branches and subroutines as functions
+    pub fn lcov(&self, rom: &[u8], load_address: u16, name: &str) -> String {
+        let analysis = Analysis::new(rom, load_address);
+        let instructions = self.instructions(&analysis, rom, load_address);
+        let mut text = format!("TN:\nSF:{}\n", name);
+
+        let functions = analysis.functions();
+        for &entry in functions.keys() {
+            writeln!(text, "FN:{},sub_{:03X}", entry, entry).unwrap();
+        }
+        for &entry in functions.keys() {
+            writeln!(text, "FNDA:{},sub_{:03X}", self.executed[entry as usize], entry).unwrap();
+        }
+        let functions_hit = functions.keys().filter(|&&entry| self.executed[entry as usize] > 0).count();
+        writeln!(text, "FNF:{}\nFNH:{}", functions.len(), functions_hit).unwrap();
+
+        let mut branches = (0, 0);
+        for &(address, _) in instructions.iter().filter(|&&(_, opcode)| is_skip(opcode)) {
+            let (skipped, not) = self.skips[address as usize];
+            // Branches of code that never ran are `-`
+            let taken = |count: u64| if self.executed[address as usize] == 0 { String::from("-") } else { count.to_string() };
+            writeln!(text, "BRDA:{},0,0,{}\nBRDA:{},0,1,{}", address, taken(skipped), address, taken(not)).unwrap();
+
+            branches.0 += 2;
+            branches.1 += (skipped > 0) as usize + (not > 0) as usize;
+        }
+        writeln!(text, "BRF:{}\nBRH:{}", branches.0, branches.1).unwrap();
+
+        for &(address, _) in &instructions {
+            writeln!(text, "DA:{},{}", address, self.executed[address as usize]).unwrap();
+        }
+        let hit = instructions.iter().filter(|&&(address, _)| self.executed[address as usize] > 0).count();
+        writeln!(text, "LF:{}\nLH:{}\nend_of_record", instructions.len(), hit).unwrap();
+
+        text
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
+
+fn is_skip(opcode: Opcode) -> bool {
+    matches!(opcode,
+        Opcode::SE(..) | Opcode::SNE(..) | Opcode::SE_V(..) | Opcode::SNE_V(..) | Opcode::SKP(_) | Opcode::SKNP(_))
+}
+
+fn percent(part: usize, total: usize) -> String {
+    if total == 0 {
+        String::from("-")
+    } else {
+        format!("{:.1}%", part as f64 * 100.0 / total as f64)
+    }
+}
+
+
+#[cfg(test)]
+fn coverage_of(run: &[(u16, u16, u16, u16)]) -> Coverage {
+    // pc, instruction, I, pc afterwards
+    let mut coverage = Coverage::new();
+    for &(pc, bytes, index, next_pc) in run {
+        let opcode = Opcode::from_bytes(bytes);
+        coverage.record(pc, opcode, index);
+        coverage.record_branch(pc, opcode, next_pc);
+    }
+    coverage
+}
+
+#[cfg(test)]
+const ROM: [u8; 12] = [
+    0xA2, 0x0A, // 200: LD I, 20A
+    0x30, 0x00, // 202: SE V0, 0
+    0xD0, 0x12, // 204: DRW V0, V1, 2
+    0x12, 0x06, // 206: JP 206
+    0x00, 0xE0, // 208: CLS, never reached
+    0xF0, 0x90, // 20A: sprite
+];
+
+#[test]
+fn test_listing() {
+    let coverage = coverage_of(&[
+        (0x200, 0xA20A, 0, 0x202),
+        (0x202, 0x3000, 0x20A, 0x204),
+        (0x204, 0xD012, 0x20A, 0x206),
+        (0x206, 0x1206, 0x20A, 0x206),
+        (0x206, 0x1206, 0x20A, 0x206),
+    ]);
+    let listing = coverage.listing(&ROM, 0x200);
+
+    assert!(listing.starts_with("4 of 4 instructions run (100.0%), 1 of 2 skip branches taken (50.0%), 2 of 4 data bytes read (50.0%)\n"));
+    assert!(listing.contains("       2  0x206  1206  JP 0x206\n"));
+    assert!(listing.contains("; skipped 0, didn't 1\n"));
+    // The unreachable CLS runs into the sprite
+    assert!(listing.contains("0x208  DB 0x00, 0xE0, 0xF0, 0x90  ; 2 of 4 read\n"));
+}
+
+#[test]
+fn test_code_found_running() {
+    // Reached by a computed jump the analysis can't follow
+    let rom = [0xB2, 0x04, 0x00, 0x00, 0x12, 0x04];
+    let coverage = coverage_of(&[(0x200, 0xB204, 0, 0x204), (0x204, 0x1204, 0, 0x204)]);
+
+    assert!(coverage.listing(&rom, 0x200).contains("       1  0x204  1204  JP 0x204\n"));
+    assert!(coverage.summary(&rom, 0x200).starts_with("2 of 2 instructions run"));
+}
+
+#[test]
+fn test_lcov() {
+    let coverage = coverage_of(&[(0x200, 0xA20A, 0, 0x202), (0x202, 0x3000, 0, 0x206)]);
+    let lcov = coverage.lcov(&ROM, 0x200, "game.ch8");
+
+    assert!(lcov.starts_with("TN:\nSF:game.ch8\nFN:512,sub_200\nFNDA:1,sub_200\nFNF:1\nFNH:1\n"));
+    assert!(lcov.contains("BRDA:514,0,0,1\nBRDA:514,0,1,0\nBRF:2\nBRH:1\n"));
+    assert!(lcov.contains("DA:516,0\n"));
+    assert!(lcov.ends_with("LF:4\nLH:2\nend_of_record\n"));
+}
+
+#[test]
+fn test_read_wraps() {
+    // A sprite drawn from the top of I's range wraps around to the bottom
+    let coverage = coverage_of(&[(0x200, 0xD012, 0xFFFF, 0x202)]);
+
+    assert!(coverage.read[0xFFF]);
+    assert!(coverage.read[0x000]);
+    assert!(!coverage.read[0x001]);
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::fs;
+
+use serde_json::{Map, Value};
+
+use crate::keymap::{HostKey, Keymap};
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+use crate::timing::Timing;
+
+// A few entries in the format of the CHIP-8 community's database,
+// https://github.com/chip-8/chip-8-database
+const PROGRAMS: &str = include_str!("../data/programs.json");
+const PLATFORMS: &str = include_str!("../data/platforms.json");
+
+// Settings for one ROM, anything the database doesn't say is left as None
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub title: String,
+    pub platform: Option<String>,
+    pub quirks: Option<Quirks>,
+    pub timing: Option<Timing>,
+    pub palette: Option<Palette>,
+    // The CHIP-8 keys the game uses, by the database's names for them like
+    // `up` or `a`
+    pub keys: Vec<(String, usize)>,
+}
+
+impl Program {
+    // Let the arrow keys, the space bar and a controller press the keys the
+    // game uses, on top of the keys that already do
+    pub fn apply_key_hints(&self, keymap: &mut Keymap) {
+        for (name, key) in &self.keys {
+            let hosts: &[&str] = match name.as_str() {
+                "up" => &["Up", "button:dpup"],
+                "down" => &["Down", "button:dpdown"],
+                "left" => &["Left", "button:dpleft"],
+                "right" => &["Right", "button:dpright"],
+                "a" => &["Space", "button:a"],
+                "b" => &["button:b"],
+                _ => continue,
+            };
+
+            keymap.add(*key, hosts.iter().map(|host| HostKey::parse(host)).collect());
+        }
+    }
+}
+
+struct Platform {
+    quirks: Map<String, Value>,
+    tickrate: Option<u32>,
+}
+
+// ROM settings looked up by the SHA-1 of the ROM
+pub struct Database {
+    platforms: HashMap<String, Platform>,
+    programs: HashMap<String, Program>,
+}
+
+impl Database {
+    pub fn new() -> Database {
+        Database {
+            platforms: HashMap::new(),
+            programs: HashMap::new(),
+        }
+    }
+
+    // The database that comes with the emulator
+    pub fn bundled() -> Database {
+        let mut database = Database::new();
+        database.add_platforms(PLATFORMS).unwrap();
+        database.add_programs(PROGRAMS).unwrap();
+        database
+    }
+
+    // Add programs from a file in the format of the community database's
+    // programs.json, replacing any the database already has
+    pub fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read ROM database {}: {}", path, e))?;
+
+        self.add_programs(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn lookup(&self, sha1: &str) -> Option<&Program> {
+        self.programs.get(&sha1.to_ascii_lowercase())
+    }
+
+    // Parse platforms in the format of platforms.json
+    pub fn add_platforms(&mut self, json: &str) -> Result<(), String> {
+        let platforms: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        for platform in platforms.as_array().ok_or("expected a list of platforms")? {
+            let id = platform["id"].as_str().ok_or("platform without an id")?;
+
+            self.platforms.insert(id.to_string(), Platform {
+                quirks: platform["quirks"].as_object().cloned().unwrap_or_default(),
+                tickrate: platform["defaultTickrate"].as_u64().map(|t| t as u32),
+            });
+        }
+
+        Ok(())
+    }
+
+    // Parse programs in the format of programs.json. Platforms have to be
+    // added first, a ROM's quirks and speed come from its platform.
+    pub fn add_programs(&mut self, json: &str) -> Result<(), String> {
+        let programs: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        for program in programs.as_array().ok_or("expected a list of programs")? {
+            let title = program["title"].as_str().unwrap_or("Unknown");
+
+            let roms = match program["roms"].as_object() {
+                Some(roms) => roms,
+                None => continue,
+            };
+
+            for (sha1, rom) in roms {
+                self.programs.insert(sha1.to_ascii_lowercase(), self.program(title, rom));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn program(&self, title: &str, rom: &Value) -> Program {
+        // A ROM can run on several platforms, the first is the one it was
+        // written for
+        let id = rom["platforms"].get(0).and_then(Value::as_str);
+        let platform = id.and_then(|id| self.platforms.get(id));
+
+        let quirks = platform.map(|platform| {
+            let mut quirks = platform.quirks.clone();
+            // Quirks the ROM needs that differ from its platform's
+            if let Some(overrides) = rom["quirkyPlatforms"][id.unwrap()].as_object() {
+                quirks.extend(overrides.clone());
+            }
+
+            let mut result = Quirks::modern();
+            result.display_wait = quirks.get("vblank").and_then(Value::as_bool).unwrap_or(false);
+            let wrap = quirks.get("wrap").and_then(Value::as_bool).unwrap_or(true);
+            result.clip_x = !wrap;
+            result.clip_y = !wrap;
+            result
+        });
+
+        // Games for the VIP get its exact timing, otherwise a fixed number
+        // of instructions per frame
+        let timing = match rom["tickrate"].as_u64() {
+            Some(tickrate) => Some(Timing::Fixed(tickrate.max(1) as u32)),
+            None if id == Some("originalChip8") || id == Some("hybridVIP") => Some(Timing::Vip),
+            None => platform.and_then(|platform| platform.tickrate).map(|t| Timing::Fixed(t.max(1))),
+        };
+
+        let palette = rom["colors"]["pixels"].as_array().and_then(|colors| {
+            let colors: Vec<&str> = colors.iter().filter_map(Value::as_str).collect();
+            Palette::parse(&colors.join(" ")).ok()
+        });
+
+        let keys = rom["keys"].as_object().map(|keys| {
+            keys.iter()
+                .filter_map(|(name, key)| match key.as_u64() {
+                    Some(key) if key < 16 => Some((name.clone(), key as usize)),
+                    _ => None,
+                })
+                .collect()
+        });
+
+        Program {
+            title: title.to_string(),
+            platform: id.map(str::to_string),
+            quirks,
+            timing,
+            palette,
+            keys: keys.unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for Database {
+    fn default() -> Database {
+        Database::bundled()
+    }
+}
+
+
+#[cfg(test)]
+const TEST_PROGRAMS: &str = r##"[
+    {
+        "title": "Test Game",
+        "roms": {
+            "AAAA": {
+                "platforms": ["xochip", "originalChip8"],
+                "tickrate": 20,
+                "colors": { "pixels": ["#000000", "#FFB000"] },
+                "keys": { "up": 5, "a": 6, "player2Up": 1 }
+            },
+            "bbbb": {
+                "platforms": ["originalChip8"],
+                "quirkyPlatforms": { "originalChip8": { "vblank": false } }
+            },
+            "cccc": {}
+        }
+    }
+]"##;
+
+#[test]
+fn test_bundled() {
+    let database = Database::bundled();
+    let tetris = database.lookup("5f518084744bf3cb8733f6e5454dfd1634320563").unwrap();
+
+    assert_eq!(tetris.title, "Tetris");
+    assert_eq!(tetris.platform.as_deref(), Some("originalChip8"));
+    assert_eq!(tetris.quirks, Some(Quirks::vip()));
+    assert_eq!(tetris.timing, Some(Timing::Vip));
+    assert!(database.lookup("0000000000000000000000000000000000000000").is_none());
+}
+
+#[test]
+fn test_programs() {
+    let mut database = Database::bundled();
+    database.add_programs(TEST_PROGRAMS).unwrap();
+
+    let program = database.lookup("aaaa").unwrap();
+    assert_eq!(program.title, "Test Game");
+    assert_eq!(program.platform.as_deref(), Some("xochip"));
+    assert_eq!(program.quirks, Some(Quirks::modern()));
+    assert_eq!(program.timing, Some(Timing::Fixed(20)));
+    assert_eq!(program.palette.unwrap().colors[1], [0xFF, 0xB0, 0x00]);
+    assert_eq!(program.keys.len(), 3);
+
+    // ROM quirks override the platform's
+    let program = database.lookup("BBBB").unwrap();
+    let mut quirks = Quirks::vip();
+    quirks.display_wait = false;
+    assert_eq!(program.quirks, Some(quirks));
+
+    let program = database.lookup("cccc").unwrap();
+    assert_eq!(program.quirks, None);
+    assert_eq!(program.timing, None);
+    assert_eq!(program.palette, None);
+}
+
+#[test]
+fn test_key_hints() {
+    let mut database = Database::new();
+    database.add_programs(TEST_PROGRAMS).unwrap();
+
+    let mut keymap = Keymap::qwerty();
+    database.lookup("aaaa").unwrap().apply_key_hints(&mut keymap);
+
+    assert_eq!(keymap.lookup(Some("Up"), None), Some(0x5));
+    assert_eq!(keymap.lookup(Some("W"), None), Some(0x5));
+    assert_eq!(keymap.lookup(Some("Space"), None), Some(0x6));
+    assert_eq!(keymap.lookup_button("dpup"), Some(0x5));
+    assert_eq!(keymap.lookup_button("a"), Some(0x6));
+}
+
+#[test]
+fn test_bad_json() {
+    assert!(Database::new().add_programs("{").is_err());
+    assert!(Database::new().add_programs("{}").is_err());
+}
@@ -0,0 +1,171 @@
+// Rendering filters that hide the flicker caused by games erasing and
+// redrawing sprites with XOR. They work on the framebuffer before it reaches
+// the canvas, so they don't need anything from the GPU.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    // Show the framebuffer as is
+    None,
+    // Lit pixels fade out over a few frames like a phosphor screen. The value
+    // is how much brightness is lost each frame, between 0 and 1.
+    Decay(f32),
+    // A pixel is lit if it was lit in either of the last two frames
+    Deflicker,
+    // Average the last two frames, pixels lit in only one are half bright
+    Blend,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "none" => Some(Filter::None),
+            "decay" => Some(Filter::Decay(0.25)),
+            "deflicker" => Some(Filter::Deflicker),
+            "blend" => Some(Filter::Blend),
+            _ => None
+        }
+    }
+}
+
+// A pixel ready to be displayed: which palette colour it shows and how
+// bright it is, from 0 (background) to 255 (fully that colour).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Pixel {
+    pub color: u8,
+    pub intensity: u8,
+}
+
+pub struct Persistence {
+    filter: Filter,
+    previous: [[u8; 64]; 32],
+    // The last colour each pixel was lit with, so it can fade out in it
+    lit: [[u8; 64]; 32],
+    glow: [[u8; 64]; 32],
+}
+
+impl Persistence {
+    pub fn new(filter: Filter) -> Persistence {
+        Persistence {
+            filter,
+            previous: [[0; 64]; 32],
+            lit: [[0; 64]; 32],
+            glow: [[0; 64]; 32],
+        }
+    }
+
+    // Whether the output changes from frame to frame even if the
+    // framebuffer doesn't, meaning it has to be redrawn every frame
+    pub fn is_animated(&self) -> bool {
+        self.filter != Filter::None
+    }
+
+    // Feed the framebuffer for this frame, should be called once per frame
+    pub fn apply(&mut self, frame: &[[u8; 64]; 32]) -> [[Pixel; 64]; 32] {
+        let mut out = [[Pixel { color: 0, intensity: 0 }; 64]; 32];
+
+        for row in 0..32 {
+            for col in 0..64 {
+                let current = frame[row][col];
+                let previous = self.previous[row][col];
+
+                out[row][col] = match self.filter {
+                    Filter::None => full(current),
+                    Filter::Deflicker => {
+                        full(if current != 0 { current } else { previous })
+                    }
+                    Filter::Blend => {
+                        match (current, previous) {
+                            (0, 0) => full(0),
+                            (0, p) => Pixel { color: p, intensity: 128 },
+                            (c, 0) => Pixel { color: c, intensity: 128 },
+                            (c, _) => full(c),
+                        }
+                    }
+                    Filter::Decay(rate) => {
+                        if current != 0 {
+                            self.lit[row][col] = current;
+                            self.glow[row][col] = 255;
+                        } else {
+                            let glow = self.glow[row][col] as f32 * (1.0 - rate);
+                            self.glow[row][col] = glow as u8;
+                        }
+
+                        Pixel { color: self.lit[row][col], intensity: self.glow[row][col] }
+                    }
+                };
+            }
+        }
+
+        self.previous = *frame;
+        out
+    }
+}
+
+fn full(color: u8) -> Pixel {
+    Pixel { color, intensity: if color != 0 { 255 } else { 0 } }
+}
+
+
+#[cfg(test)]
+fn frame_with(row: usize, col: usize) -> [[u8; 64]; 32] {
+    let mut frame = [[0; 64]; 32];
+    frame[row][col] = 1;
+    frame
+}
+
+#[test]
+fn test_filter_none() {
+    let mut persistence = Persistence::new(Filter::None);
+
+    persistence.apply(&frame_with(1, 1));
+    let out = persistence.apply(&[[0; 64]; 32]);
+
+    assert_eq!(out[1][1].intensity, 0);
+}
+
+#[test]
+fn test_filter_deflicker() {
+    let mut persistence = Persistence::new(Filter::Deflicker);
+
+    persistence.apply(&frame_with(1, 1));
+    let out = persistence.apply(&[[0; 64]; 32]);
+    assert_eq!(out[1][1], Pixel { color: 1, intensity: 255 });
+
+    // Only the last two frames count
+    let out = persistence.apply(&[[0; 64]; 32]);
+    assert_eq!(out[1][1].intensity, 0);
+}
+
+#[test]
+fn test_filter_blend() {
+    let mut persistence = Persistence::new(Filter::Blend);
+
+    let out = persistence.apply(&frame_with(1, 1));
+    assert_eq!(out[1][1].intensity, 128);
+
+    let out = persistence.apply(&frame_with(1, 1));
+    assert_eq!(out[1][1].intensity, 255);
+
+    let out = persistence.apply(&[[0; 64]; 32]);
+    assert_eq!(out[1][1].intensity, 128);
+}
+
+#[test]
+fn test_filter_decay() {
+    let mut persistence = Persistence::new(Filter::Decay(0.5));
+
+    let out = persistence.apply(&frame_with(1, 1));
+    assert_eq!(out[1][1], Pixel { color: 1, intensity: 255 });
+
+    let out = persistence.apply(&[[0; 64]; 32]);
+    assert_eq!(out[1][1], Pixel { color: 1, intensity: 127 });
+
+    let out = persistence.apply(&[[0; 64]; 32]);
+    assert_eq!(out[1][1], Pixel { color: 1, intensity: 63 });
+}
+
+#[test]
+fn test_filter_from_name() {
+    assert_eq!(Filter::from_name("blend"), Some(Filter::Blend));
+    assert_eq!(Filter::from_name("crt"), None);
+}
@@ -0,0 +1,56 @@
+// Something the user asked for besides pressing CHIP-8 keys
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Action {
+    Quit,
+    Screenshot,
+    // Start recording frames, or stop and save the recording
+    ToggleRecording,
+    // Restart the program keeping memory as it is
+    SoftReset,
+    // Restart with memory cleared and the ROM loaded again
+    HardReset,
+    TogglePause,
+    // Run one frame and stay paused
+    FrameAdvance,
+    // Fast forward starts or stops, usually while a key is held
+    FastForward(bool),
+    ToggleSlowMotion,
+    // Write a byte to memory, only done while paused
+    Poke(u16, u8),
+}
+
+// Where the emulator gets its input from and shows its output
+pub trait Frontend {
+    // Update the keypad from pending input and report any other actions
+    fn handle_events(&mut self, keyboard: &mut [bool; 16]) -> Vec<Action>;
+
+    // Whether draw has to be called even though the framebuffer didn't change
+    fn needs_redraw(&self) -> bool;
+
+    fn draw(&mut self, screen: &[[u8; 64]; 32]);
+
+    // Called every frame with whether the sound timer is running
+    fn beep(&mut self, playing: bool);
+
+    // Show a short status like the emulation speed, empty for none
+    fn set_status(&mut self, status: &str);
+
+    // Briefly show a message like "Screenshot saved"
+    fn show_message(&mut self, text: &str);
+
+    // A line for the terminal like where a screenshot was saved, errors go
+    // to stderr
+    fn log(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    fn log_error(&mut self, text: &str) {
+        eprintln!("{}", text);
+    }
+
+    // Emulated frames and instructions per second, for frontends that show them
+    fn set_stats(&mut self, _fps: f32, _ips: f32) {}
+
+    // Called every frame for frontends with a memory viewer
+    fn show_memory(&mut self, _memory: &[u8; 4096], _pc: u16, _index: u16) {}
+}
@@ -1,16 +1,95 @@
-use sdl2::video::Window;
-use sdl2::render::Canvas;
+use sdl2::video::{Window, FullscreenType};
+use sdl2::render::{BlendMode, Canvas};
 use sdl2::Sdl;
 use sdl2::rect::Rect;
 use sdl2::pixels::Color;
 
-use std::ops::{Index, IndexMut};
+use crate::palette::Palette;
+use crate::filter::{Filter, Persistence, Pixel};
+use crate::memview::{MemoryView, Mark, BYTES_PER_ROW};
+use crate::osd::{self, Label, GLYPH_HEIGHT};
+
+const TITLE: &str = "Chip-8 emulator";
+
+// How the framebuffer is stretched to fill the window
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Scaling {
+    // Every CHIP-8 pixel is the same whole number of window pixels
+    Integer,
+    // Use as much of the window as possible, pixels may differ by one
+    Fractional,
+}
+
+// Where the framebuffer goes in the window, bars fill the rest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub scale: f32,
+}
+
+impl Viewport {
+    // Fit a framebuffer into a window keeping its aspect ratio, centered
+    pub fn fit(window: (u32, u32), framebuffer: (u32, u32), scaling: Scaling) -> Viewport {
+        let (win_w, win_h) = window;
+        let (fb_w, fb_h) = framebuffer;
+
+        let mut scale = (win_w as f32 / fb_w as f32).min(win_h as f32 / fb_h as f32);
+        if scaling == Scaling::Integer {
+            scale = scale.floor().max(1.0);
+        }
+
+        let width = (fb_w as f32 * scale) as i32;
+        let height = (fb_h as f32 * scale) as i32;
+
+        Viewport {
+            x: (win_w as i32 - width) / 2,
+            y: (win_h as i32 - height) / 2,
+            scale,
+        }
+    }
+
+    // The window rectangle covering framebuffer pixel (col, row). Edges are
+    // rounded separately so fractional scales leave no gaps between pixels.
+    pub fn rect(&self, col: usize, row: usize) -> Rect {
+        let x0 = (col as f32 * self.scale) as i32;
+        let x1 = ((col + 1) as f32 * self.scale) as i32;
+        let y0 = (row as f32 * self.scale) as i32;
+        let y1 = ((row + 1) as f32 * self.scale) as i32;
+
+        Rect::new(self.x + x0, self.y + y0, (x1 - x0) as u32, (y1 - y0) as u32)
+    }
+
+    // The window rectangle covering the whole framebuffer
+    pub fn bounds(&self, framebuffer: (u32, u32)) -> Rect {
+        let top_left = self.rect(0, 0);
+        let bottom_right = self.rect(framebuffer.0 as usize - 1, framebuffer.1 as usize - 1);
+
+        Rect::new(
+            top_left.x(),
+            top_left.y(),
+            (bottom_right.right() - top_left.x()) as u32,
+            (bottom_right.bottom() - top_left.y()) as u32
+        )
+    }
+}
+
+// The width and height of a frame, 64x32 or 128x64 in hi-res
+pub fn frame_size<T, R: AsRef<[T]>>(frame: &[R]) -> (u32, u32) {
+    let width = frame.first().map_or(0, |row| row.as_ref().len());
+
+    (width as u32, frame.len() as u32)
+}
 
 
 pub struct Screen {
-    screen: [[u8; 64]; 32],
     canvas: Canvas<Window>,
-    pixel_size: u32
+    scaling: Scaling,
+    viewport: Viewport,
+    // The size of the last frame drawn, which the viewport is fitted to
+    framebuffer: (u32, u32),
+    palette: Palette,
+    persistence: Persistence
 }
 
 impl Screen {
@@ -19,8 +98,9 @@ impl Screen {
         let video_subsystem = sdl_context.video().unwrap();
 
         let (width, height) = (1280, 640);
-        let window = video_subsystem.window("Chip-8 emulator", width, height)
+        let window = video_subsystem.window(TITLE, width, height)
             .position_centered()
+            .resizable()
             .build()
             .unwrap();
         
@@ -30,46 +110,319 @@ impl Screen {
         canvas.present();
 
         Screen {
-            screen: [[0; 64]; 32],
             canvas,
-            pixel_size: width / 64
+            scaling: Scaling::Integer,
+            viewport: Viewport::fit((width, height), (64, 32), Scaling::Integer),
+            framebuffer: (64, 32),
+            palette: Palette::default(),
+            persistence: Persistence::new(Filter::None)
         }
     }
 
-    pub fn draw(&mut self) {
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+        self.resize();
+    }
+
+    // Recompute where the framebuffer goes after the window changed size
+    pub fn resize(&mut self) {
+        let size = self.canvas.output_size().unwrap();
+
+        self.viewport = Viewport::fit(size, self.framebuffer, self.scaling);
+    }
+
+    pub fn framebuffer_size(&self) -> (u32, u32) {
+        self.framebuffer
+    }
+
+    pub fn set_window_size(&mut self, width: u32, height: u32) {
+        self.canvas.window_mut().set_size(width, height).unwrap();
+        self.resize();
+    }
+
+    // Show a status after the name in the title bar
+    pub fn set_status(&mut self, status: &str) {
+        let title = if status.is_empty() {
+            TITLE.to_string()
+        } else {
+            format!("{} - {}", TITLE, status)
+        };
+
+        self.canvas.window_mut().set_title(&title).unwrap();
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let mode = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+
+        window.set_fullscreen(mode).unwrap();
+        self.resize();
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.persistence = Persistence::new(filter);
+    }
+
+    // Whether the screen has to be redrawn every frame, not only on changes
+    pub fn is_animated(&self) -> bool {
+        self.persistence.is_animated()
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    // Draw the framebuffer with the OSD's labels on top
+    pub fn draw(&mut self, screen: &[[u8; 64]; 32], labels: &[Label]) {
+        let pixels = self.persistence.apply(screen);
+        self.draw_pixels(&pixels);
+
+        self.draw_labels(labels);
+        self.canvas.present();
+    }
+
+    // Draw a frame of any size, refitting the viewport when it changes
+    fn draw_pixels<R: AsRef<[Pixel]>>(&mut self, pixels: &[R]) {
+        let size = frame_size(pixels);
+        if size != self.framebuffer {
+            self.framebuffer = size;
+            self.resize();
+        }
+
         self.canvas.set_draw_color(Color::BLACK);
         self.canvas.clear();
 
-        self.canvas.set_draw_color(Color::WHITE);
-        for row in 0..32 {
-            for col in 0..64 {
-                if self.screen[row][col] == 1 {
-                    self.canvas.fill_rect(Rect::new(
-                        col as i32 * self.pixel_size as i32,
-                        row as i32 * self.pixel_size as i32,
-                        self.pixel_size,
-                        self.pixel_size
-                    )).unwrap();
+        self.canvas.set_draw_color(self.color(Pixel { color: 0, intensity: 0 }));
+        self.canvas.fill_rect(self.viewport.bounds(self.framebuffer)).unwrap();
+
+        for (row, line) in pixels.iter().enumerate() {
+            for (col, &pixel) in line.as_ref().iter().enumerate() {
+                if pixel.intensity != 0 {
+                    self.canvas.set_draw_color(self.color(pixel));
+                    self.canvas.fill_rect(self.viewport.rect(col, row)).unwrap();
                 }
             }
         }
+    }
 
-        self.canvas.present();
+    // OSD text gets bigger with the game but stays readable in small windows
+    pub fn osd_scale(&self) -> i32 {
+        ((self.viewport.scale / 5.0) as i32).max(2)
+    }
+
+    // The size of the game area in OSD pixels, which the OSD is laid out on
+    pub fn osd_size(&self) -> (i32, i32) {
+        let (width, height) = self.framebuffer;
+        let size = ((self.viewport.scale * width as f32) as i32, (self.viewport.scale * height as f32) as i32);
+
+        (size.0 / self.osd_scale(), size.1 / self.osd_scale())
+    }
+
+    // Each label is white text on a see-through black box, or the other way
+    // round when highlighted
+    fn draw_labels(&mut self, labels: &[Label]) {
+        let scale = self.osd_scale();
+        self.canvas.set_blend_mode(BlendMode::Blend);
+
+        for label in labels {
+            let (background, text) = if label.highlight {
+                (Color::RGBA(255, 255, 255, 224), Color::BLACK)
+            } else {
+                (Color::RGBA(0, 0, 0, 160), Color::WHITE)
+            };
+
+            let x = self.viewport.x + label.x * scale;
+            let y = self.viewport.y + label.y * scale;
+            let width = osd::text_width(&label.text) + 2;
+            let height = GLYPH_HEIGHT + 2;
+
+            self.canvas.set_draw_color(background);
+            self.canvas.fill_rect(Rect::new(x - scale, y - scale, (width * scale) as u32, (height * scale) as u32)).unwrap();
+
+            self.canvas.set_draw_color(text);
+            draw_text(&mut self.canvas, x, y, scale, &label.text);
+        }
+
+        self.canvas.set_blend_mode(BlendMode::None);
     }
+
+    // Mix the pixel's colour into the background by its intensity
+    fn color(&self, pixel: Pixel) -> Color {
+        let background = self.palette.colors[0];
+        let foreground = self.palette.colors[pixel.color as usize & 0b11];
+
+        let mut rgb = [0; 3];
+        for i in 0..3 {
+            let (b, f) = (background[i] as u32, foreground[i] as u32);
+            rgb[i] = ((b * (255 - pixel.intensity as u32) + f * pixel.intensity as u32) / 255) as u8;
+        }
+
+        Color::RGB(rgb[0], rgb[1], rgb[2])
+    }
+}
+
+
+// Text in the OSD font, `scale` window pixels to a font pixel, in the
+// canvas's current colour
+fn draw_text(canvas: &mut Canvas<Window>, x: i32, y: i32, scale: i32, text: &str) {
+    let rects: Vec<Rect> = osd::text_pixels(text).iter()
+        .map(|&(px, py)| Rect::new(x + px * scale, y + py * scale, scale as u32, scale as u32))
+        .collect();
+
+    canvas.fill_rects(&rects).unwrap();
+}
+
+
+// Window pixels per font pixel in the memory window
+const MEMORY_SCALE: i32 = 2;
+// Rows of the dump in view
+pub const MEMORY_ROWS: usize = 32;
+const CHAR_WIDTH: i32 = osd::GLYPH_WIDTH + 1;
+const LINE_HEIGHT: i32 = GLYPH_HEIGHT + 3;
+// Columns, in characters, of the bytes in hex and as text
+const HEX_COLUMN: i32 = 6;
+const TEXT_COLUMN: i32 = HEX_COLUMN + BYTES_PER_ROW as i32 * 3 + 2;
+const MEMORY_COLUMNS: i32 = TEXT_COLUMN + BYTES_PER_ROW as i32 + 1;
+
+// A second window with a hex dump of memory
+pub struct MemoryWindow {
+    canvas: Canvas<Window>,
 }
 
+impl MemoryWindow {
+    pub fn new(sdl_context: &Sdl) -> MemoryWindow {
+        let video_subsystem = sdl_context.video().unwrap();
 
-impl Index<usize> for Screen {
-    type Output = [u8; 64];
+        let width = (MEMORY_COLUMNS * CHAR_WIDTH + 2) * MEMORY_SCALE;
+        let height = (MEMORY_ROWS as i32 * LINE_HEIGHT + 2) * MEMORY_SCALE;
+        let window = video_subsystem.window(&format!("{} - Memory", TITLE), width as u32, height as u32)
+            .build()
+            .unwrap();
 
-    fn index(&self, index: usize) -> &[u8; 64] {
-        &self.screen[index]
+        MemoryWindow {
+            canvas: window.into_canvas().build().unwrap(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    // Each row is the address, the bytes in hex, then as text. The font is
+    // on blue, pc on green and I on yellow, bytes that changed are red.
+    pub fn draw(&mut self, view: &MemoryView) {
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+
+        for (row, line) in view.lines().iter().enumerate() {
+            let y = (row as i32 * LINE_HEIGHT + 2) * MEMORY_SCALE;
+
+            self.canvas.set_draw_color(Color::GRAY);
+            draw_text(&mut self.canvas, 2 * MEMORY_SCALE, y, MEMORY_SCALE, &format!("{:03X}", line.address));
+
+            for (i, &(value, mark)) in line.bytes.iter().enumerate() {
+                let hex = match view.pending() {
+                    Some(high) if mark == Mark::Cursor => format!("{:X}_", high),
+                    _ => format!("{:02X}", value),
+                };
+                let text = match value {
+                    0x20..=0x7E => (value as char).to_string(),
+                    _ => String::from("."),
+                };
+
+                // A gap halfway along the row
+                let hex_column = HEX_COLUMN + i as i32 * 3 + (i >= BYTES_PER_ROW / 2) as i32;
+                self.draw_byte(hex_column, y, &hex, mark);
+                self.draw_byte(TEXT_COLUMN + i as i32, y, &text, mark);
+            }
+        }
+
+        self.canvas.present();
+    }
+
+    fn draw_byte(&mut self, column: i32, y: i32, text: &str, mark: Mark) {
+        let x = (column * CHAR_WIDTH + 2) * MEMORY_SCALE;
+
+        let (background, foreground) = match mark {
+            Mark::Plain => (None, Color::WHITE),
+            Mark::Font => (Some(Color::RGB(0, 0, 128)), Color::WHITE),
+            Mark::Changed => (None, Color::RGB(255, 64, 64)),
+            Mark::Index => (Some(Color::RGB(192, 192, 0)), Color::BLACK),
+            Mark::Pc => (Some(Color::RGB(0, 160, 0)), Color::BLACK),
+            Mark::Cursor => (Some(Color::WHITE), Color::BLACK),
+        };
+
+        if let Some(background) = background {
+            let width = osd::text_width(text) + 2;
+            self.canvas.set_draw_color(background);
+            self.canvas.fill_rect(Rect::new(
+                x - MEMORY_SCALE,
+                y - MEMORY_SCALE,
+                (width * MEMORY_SCALE) as u32,
+                ((GLYPH_HEIGHT + 2) * MEMORY_SCALE) as u32
+            )).unwrap();
+        }
+
+        self.canvas.set_draw_color(foreground);
+        draw_text(&mut self.canvas, x, y, MEMORY_SCALE, text);
     }
 }
 
-impl IndexMut<usize> for Screen {
 
-    fn index_mut(&mut self, index: usize) -> &mut [u8; 64] {
-        &mut self.screen[index]
+#[test]
+fn test_viewport_exact_fit() {
+    let viewport = Viewport::fit((1280, 640), (64, 32), Scaling::Integer);
+
+    assert_eq!(viewport, Viewport { x: 0, y: 0, scale: 20.0 });
+}
+
+#[test]
+fn test_viewport_letterbox() {
+    // Too tall, bars above and below
+    let viewport = Viewport::fit((640, 640), (64, 32), Scaling::Fractional);
+    assert_eq!(viewport, Viewport { x: 0, y: 160, scale: 10.0 });
+
+    // Too wide, bars left and right
+    let viewport = Viewport::fit((1000, 320), (64, 32), Scaling::Fractional);
+    assert_eq!(viewport, Viewport { x: 180, y: 0, scale: 10.0 });
+}
+
+#[test]
+fn test_viewport_integer_scaling() {
+    let viewport = Viewport::fit((700, 400), (64, 32), Scaling::Integer);
+    assert_eq!(viewport, Viewport { x: 30, y: 40, scale: 10.0 });
+
+    let viewport = Viewport::fit((700, 400), (64, 32), Scaling::Fractional);
+    assert_eq!(viewport.scale, 10.9375);
+}
+
+#[test]
+fn test_viewport_hires() {
+    let viewport = Viewport::fit((1280, 640), (128, 64), Scaling::Integer);
+
+    assert_eq!(viewport, Viewport { x: 0, y: 0, scale: 10.0 });
+}
+
+#[test]
+fn test_hires_frame() {
+    let frame = [[0u8; 128]; 64];
+    let size = frame_size(&frame);
+    assert_eq!(size, (128, 64));
+
+    let viewport = Viewport::fit((1000, 700), size, Scaling::Integer);
+    assert_eq!(viewport, Viewport { x: 52, y: 126, scale: 7.0 });
+    assert_eq!(viewport.bounds(size), Rect::new(52, 126, 896, 448));
+    assert_eq!(viewport.rect(127, 63), Rect::new(52 + 889, 126 + 441, 7, 7));
+}
+
+#[test]
+fn test_viewport_fractional_rects_have_no_gaps() {
+    let viewport = Viewport::fit((700, 400), (64, 32), Scaling::Fractional);
+
+    for col in 0..63 {
+        assert_eq!(viewport.rect(col, 0).right(), viewport.rect(col + 1, 0).left());
     }
-}
\ No newline at end of file
+}
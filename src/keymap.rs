@@ -0,0 +1,269 @@
+use std::fs;
+use std::path::Path;
+
+// A host key, by what it types or by where it is on the keyboard
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum HostKey {
+    // An SDL key name like `Q` or `Up`, follows the keyboard layout
+    Key(String),
+    // An SDL scancode name, the key in that position on a US keyboard
+    // whatever the layout
+    Scancode(String),
+    // An SDL game controller button name like `a` or `dpup`
+    Button(String),
+}
+
+impl HostKey {
+    // `scancode:Q` is a scancode, `button:a` a controller button and
+    // anything else is a key name
+    pub fn parse(name: &str) -> HostKey {
+        if let Some(scancode) = name.strip_prefix("scancode:") {
+            HostKey::Scancode(scancode.trim().to_string())
+        } else if let Some(button) = name.strip_prefix("button:") {
+            HostKey::Button(button.trim().to_string())
+        } else {
+            HostKey::Key(name.trim().to_string())
+        }
+    }
+
+    // Names are matched ignoring case
+    fn same_as(&self, other: &HostKey) -> bool {
+        match (self, other) {
+            (HostKey::Key(a), HostKey::Key(b)) |
+            (HostKey::Scancode(a), HostKey::Scancode(b)) |
+            (HostKey::Button(a), HostKey::Button(b)) => a.eq_ignore_ascii_case(b),
+            _ => false,
+        }
+    }
+}
+
+// Which host keys press which CHIP-8 keys, a CHIP-8 key can have several
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Keymap {
+    bindings: Vec<(HostKey, usize)>,
+}
+
+impl Keymap {
+    // Keys 0 to F in order across the left of a QWERTY keyboard:
+    // 1234/QWER/ASDF/ZXCV. The controller's D-pad presses 2/4/6/8, the
+    // arrows on the keypad, A presses 5 in the middle and B presses 0.
+    pub fn qwerty() -> Keymap {
+        const KEYS: [(&str, usize); 16] = [
+            ("1", 0x0), ("2", 0x1), ("3", 0x2), ("4", 0x3),
+            ("Q", 0x4), ("W", 0x5), ("E", 0x6), ("R", 0x7),
+            ("A", 0x8), ("S", 0x9), ("D", 0xA), ("F", 0xB),
+            ("Z", 0xC), ("X", 0xD), ("C", 0xE), ("V", 0xF),
+        ];
+
+        const BUTTONS: [(&str, usize); 6] = [
+            ("dpup", 0x2), ("dpleft", 0x4), ("dpright", 0x6), ("dpdown", 0x8),
+            ("a", 0x5), ("b", 0x0),
+        ];
+
+        let keys = KEYS.iter().map(|&(name, key)| (HostKey::Key(name.to_string()), key));
+        let buttons = BUTTONS.iter().map(|&(name, key)| (HostKey::Button(name.to_string()), key));
+
+        Keymap {
+            bindings: keys.chain(buttons).collect(),
+        }
+    }
+
+    // Apply the bindings in a keymap file on top of these ones
+    pub fn apply_file(&mut self, path: &str, rom: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read keymap {}: {}", path, e))?;
+
+        self.apply(&text, rom)
+    }
+
+    // Parse a keymap file. Each line binds a CHIP-8 key to a comma separated
+    // list of host keys, starting from the QWERTY layout:
+    //
+    //   # Arrow keys as well as WASD
+    //   5 = W, Up
+    //   8 = scancode:S, Down
+    //
+    //   [tetris]
+    //   4 = Left, button:dpleft
+    //
+    // Lines after a `[name]` header only apply to the ROM with that file
+    // name, without the extension.
+    pub fn parse(text: &str, rom: &str) -> Result<Keymap, String> {
+        let mut keymap = Keymap::qwerty();
+        keymap.apply(text, rom)?;
+
+        Ok(keymap)
+    }
+
+    // Apply the bindings in a keymap file's text on top of these ones
+    pub fn apply(&mut self, text: &str, rom: &str) -> Result<(), String> {
+        let rom = Path::new(rom).file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+
+        let mut applies = true;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                applies = line[1..line.len()-1].trim() == rom;
+                continue;
+            }
+
+            let (key, hosts) = match line.find('=') {
+                Some(i) => (line[..i].trim(), &line[i+1..]),
+                None => return Err(format!("line {}: expected KEY = HOST KEYS", number + 1)),
+            };
+
+            let key = match usize::from_str_radix(key, 16) {
+                Ok(key) if key < 16 => key,
+                _ => return Err(format!("line {}: invalid CHIP-8 key {}", number + 1, key)),
+            };
+
+            if applies {
+                let hosts = hosts.split(',').map(str::trim).filter(|h| !h.is_empty());
+                self.bind(key, hosts.map(HostKey::parse).collect());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Replace the host keys that press a CHIP-8 key, taking them off any
+    // other key they pressed
+    pub fn bind(&mut self, key: usize, hosts: Vec<HostKey>) {
+        self.bindings.retain(|&(_, k)| k != key);
+        self.add(key, hosts);
+    }
+
+    // Add host keys to the ones that press a CHIP-8 key
+    pub fn add(&mut self, key: usize, hosts: Vec<HostKey>) {
+        self.bindings.retain(|(host, _)| !hosts.iter().any(|h| h.same_as(host)));
+        self.bindings.extend(hosts.into_iter().map(|host| (host, key)));
+    }
+
+    // The CHIP-8 key pressed or released by a host key, given its SDL key and
+    // scancode names. Names are matched ignoring case. The latest binding
+    // wins, so a scancode from a keymap file beats a default key name that
+    // happens to match too.
+    pub fn lookup(&self, key: Option<&str>, scancode: Option<&str>) -> Option<usize> {
+        self.bindings.iter().rev().find_map(|(host, k)| {
+            let matches = match (host, key, scancode) {
+                (HostKey::Key(name), Some(key), _) => name.eq_ignore_ascii_case(key),
+                (HostKey::Scancode(name), _, Some(scancode)) => name.eq_ignore_ascii_case(scancode),
+                _ => false,
+            };
+
+            if matches { Some(*k) } else { None }
+        })
+    }
+
+    // The CHIP-8 key pressed or released by a controller button
+    pub fn lookup_button(&self, button: &str) -> Option<usize> {
+        self.bindings.iter().rev().find_map(|(host, k)| match host {
+            HostKey::Button(name) if name.eq_ignore_ascii_case(button) => Some(*k),
+            _ => None,
+        })
+    }
+
+    // The CHIP-8 key for a character typed in a terminal, only single
+    // character key names can match
+    pub fn lookup_char(&self, byte: u8) -> Option<usize> {
+        self.lookup(Some(&(byte as char).to_string()), None)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::qwerty()
+    }
+}
+
+
+#[test]
+fn test_qwerty() {
+    let keymap = Keymap::qwerty();
+
+    assert_eq!(keymap.lookup(Some("1"), None), Some(0x0));
+    assert_eq!(keymap.lookup(Some("x"), None), Some(0xD));
+    assert_eq!(keymap.lookup(Some("V"), Some("V")), Some(0xF));
+    assert_eq!(keymap.lookup(Some("P"), None), None);
+}
+
+#[test]
+fn test_parse_multiple_host_keys() {
+    let keymap = Keymap::parse("5 = W, Up\nC = scancode:Z # comment\n", "game.ch8").unwrap();
+
+    assert_eq!(keymap.lookup(Some("W"), None), Some(0x5));
+    assert_eq!(keymap.lookup(Some("Up"), None), Some(0x5));
+    assert_eq!(keymap.lookup(Some("Y"), Some("Z")), Some(0xC));
+    // Rebinding a key drops its old host keys, others are kept
+    assert_eq!(keymap.lookup(Some("Z"), Some("Y")), None);
+    assert_eq!(keymap.lookup(Some("Q"), None), Some(0x4));
+}
+
+#[test]
+fn test_parse_rom_sections() {
+    let text = "4 = Q\n[tetris]\n4 = Left\n[pong]\n4 = Up\n";
+
+    let keymap = Keymap::parse(text, "roms/tetris.rom").unwrap();
+    assert_eq!(keymap.lookup(Some("Left"), None), Some(0x4));
+    assert_eq!(keymap.lookup(Some("Up"), None), None);
+
+    let keymap = Keymap::parse(text, "roms/breakout.ch8").unwrap();
+    assert_eq!(keymap.lookup(Some("Left"), None), None);
+    assert_eq!(keymap.lookup(Some("Q"), None), Some(0x4));
+}
+
+#[test]
+fn test_rebind_host_key() {
+    // AZERTY puts A where QWERTY has Q
+    let keymap = Keymap::parse("4 = A", "game.ch8").unwrap();
+
+    assert_eq!(keymap.lookup(Some("A"), None), Some(0x4));
+    assert_eq!(keymap.lookup(Some("Q"), None), None);
+    assert_eq!(keymap.lookup(Some("S"), None), Some(0x9));
+}
+
+#[test]
+fn test_scancode_beats_default_key() {
+    // On AZERTY the key in QWERTY's Q position types A, which presses 8 by
+    // default
+    let keymap = Keymap::parse("4 = scancode:Q", "game.ch8").unwrap();
+
+    assert_eq!(keymap.lookup(Some("A"), Some("Q")), Some(0x4));
+    assert_eq!(keymap.lookup(Some("A"), None), Some(0x8));
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(Keymap::parse("5 W", "").is_err());
+    assert!(Keymap::parse("G = W", "").is_err());
+    assert!(Keymap::parse("10 = W", "").is_err());
+}
+
+#[test]
+fn test_buttons() {
+    let keymap = Keymap::qwerty();
+    assert_eq!(keymap.lookup_button("dpup"), Some(0x2));
+    assert_eq!(keymap.lookup_button("start"), None);
+    // Buttons and keys don't share names
+    assert_eq!(keymap.lookup(Some("a"), None), Some(0x8));
+
+    // Binding a host key moves it off the key it pressed before
+    let keymap = Keymap::parse("[tetris]\n5 = W, button:dpleft\nE = E, button:A", "tetris.rom").unwrap();
+    assert_eq!(keymap.lookup_button("dpleft"), Some(0x5));
+    assert_eq!(keymap.lookup_button("a"), Some(0xE));
+    assert_eq!(keymap.lookup(Some("W"), None), Some(0x5));
+    assert_eq!(keymap.lookup(Some("Q"), None), Some(0x4));
+}
+
+#[test]
+fn test_lookup_char() {
+    let keymap = Keymap::qwerty();
+
+    assert_eq!(keymap.lookup_char(b'q'), Some(0x4));
+    assert_eq!(keymap.lookup_char(b'p'), None);
+}
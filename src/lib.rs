@@ -1,5 +1,30 @@
 mod util;
 mod opcode;
-mod graphics;
+#[cfg(test)]
+mod spec;
 
-pub mod chip8;
\ No newline at end of file
+pub mod chip8;
+pub mod rom;
+pub mod timing;
+pub mod quirks;
+pub mod palette;
+pub mod filter;
+pub mod frontend;
+pub mod pacer;
+pub mod keymap;
+pub mod database;
+pub mod config;
+pub mod screenshot;
+pub mod recording;
+pub mod osd;
+pub mod memview;
+pub mod cheats;
+pub mod analysis;
+pub mod profiler;
+pub mod coverage;
+pub mod octo;
+#[cfg(feature = "sdl")]
+pub mod graphics;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+pub mod tty;
\ No newline at end of file
@@ -1,12 +1,621 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use chip8::cheats::{self, Cheats, Console};
+use chip8::analysis::Analysis;
 use chip8::chip8::Chip8;
+#[cfg(feature = "sdl")]
+use chip8::config::Audio;
+use chip8::config::Config;
+use chip8::database::Database;
+#[cfg(feature = "sdl")]
+use chip8::filter::Filter;
+use chip8::frontend::{Action, Frontend};
+#[cfg(feature = "sdl")]
+use chip8::graphics::Scaling;
+use chip8::keymap::Keymap;
+use chip8::pacer::Pacer;
+use chip8::palette::Palette;
+use chip8::quirks::Quirks;
+use chip8::recording::Recorder;
+use chip8::rom::{self, Watcher, MAX_LOAD_ADDRESS};
+use chip8::screenshot::{self, Format};
+#[cfg(feature = "sdl")]
+use chip8::sdl::SdlFrontend;
+use chip8::timing::{Timing, MAX_IPS};
+use chip8::tty::{TtyFrontend, TtyMode};
+
+// How much screenshots are scaled up from 64x32
+const SCREENSHOT_SCALE: usize = 10;
+// And recordings, 640x320
+const RECORDING_SCALE: usize = 10;
+// How often --watch checks whether the ROM changed
+const WATCH_FRAMES: u32 = 30;
+// The host shows a frame every 60th of a second, whatever the emulation speed
+const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+// How many addresses and subroutines the profile lists
+const PROFILE_ROWS: usize = 20;
+// How often the FPS and IPS counters are updated
+const STATS_TIME: Duration = Duration::from_secs(1);
+
+enum Recording {
+    Gif(PathBuf),
+    // A shell command raw frames are piped into
+    Pipe(String),
+}
+
+// Settings given on the command line or changed from their defaults in the
+// config file, which win over the ROM database
+struct Explicit {
+    timing: bool,
+    quirks: bool,
+    palette: bool,
+}
+
+struct Options {
+    rom: String,
+    load_address: u16,
+    timing: Timing,
+    quirks: Quirks,
+    palette: Palette,
+    keymap: Keymap,
+    keymap_file: Option<String>,
+    database: Option<String>,
+    explicit: Explicit,
+    #[cfg(feature = "sdl")]
+    filter: Filter,
+    #[cfg(feature = "sdl")]
+    scaling: Scaling,
+    #[cfg(feature = "sdl")]
+    scale: u32,
+    #[cfg(feature = "sdl")]
+    audio: Audio,
+    tty: Option<TtyMode>,
+    screenshot_dir: PathBuf,
+    screenshot_format: Format,
+    recording_dir: PathBuf,
+    record: Option<Recording>,
+    // Reload the ROM when it changes on disk
+    watch: bool,
+    pacer: Pacer,
+    cheat_dir: PathBuf,
+    // Read cheat commands from stdin
+    cheat_console: bool,
+    // Where the profiler's report and folded call stacks go when it quits
+    profile: Option<PathBuf>,
+    profile_folded: Option<PathBuf>,
+    // Where the annotated disassembly and lcov tracefile go when it quits
+    coverage: Option<PathBuf>,
+    lcov: Option<PathBuf>,
+}
 
 fn main() {
+    let mut options = parse_args();
+
     let mut emu = Chip8::new();
-    emu.load_game("roms/tetris.rom");
+    emu.set_load_address(options.load_address);
+    emu.load_game(&options.rom).unwrap_or_else(|e| fail(&e));
+
+    configure_for_rom(&mut options, emu.rom_sha1());
+    emu.set_timing(options.timing);
+    emu.set_quirks(options.quirks);
+
+    let cheat_file = cheats::file_name(&options.cheat_dir, emu.rom_sha1());
+    let cheats = Cheats::load(&cheat_file).unwrap_or_else(|e| fail(&e));
+    if !cheats.cheats().is_empty() {
+        println!("Loaded {} cheats from {}", cheats.cheats().len(), cheat_file.display());
+    }
+    cheats.apply(&mut emu);
+    let mut console = Console::new(cheats, cheat_file);
 
-    loop {
-        emu.handle_events();
-        emu.tick();
-        std::thread::sleep(std::time::Duration::from_secs_f64(1.0/60.0));
+    if options.profile.is_some() || options.profile_folded.is_some() {
+        emu.enable_profiler();
     }
+    if options.coverage.is_some() || options.lcov.is_some() {
+        emu.enable_coverage();
+    }
+
+    match options.tty {
+        Some(mode) => {
+            let mut frontend = TtyFrontend::new(mode);
+            frontend.set_keymap(options.keymap.clone());
+
+            run(&mut emu, &mut frontend, &mut console, &options);
+        }
+        #[cfg(feature = "sdl")]
+        None => {
+            let mut frontend = SdlFrontend::new();
+            frontend.set_keymap(options.keymap.clone());
+            frontend.set_palette(options.palette);
+            frontend.set_filter(options.filter);
+            frontend.set_scaling(options.scaling);
+            frontend.set_window_scale(options.scale);
+            frontend.set_audio(options.audio.tone, options.audio.volume);
+
+            run(&mut emu, &mut frontend, &mut console, &options);
+        }
+        #[cfg(not(feature = "sdl"))]
+        None => unreachable!(),
+    }
+
+    if let Some(profiler) = emu.profiler() {
+        save_report(&options.profile, &profiler.report(PROFILE_ROWS), "profile");
+        save_report(&options.profile_folded, &profiler.folded(), "profile");
+    }
+    if let Some(coverage) = emu.coverage() {
+        print!("Coverage: {}", coverage.summary(emu.rom(), emu.load_address()));
+        save_report(&options.coverage, &coverage.listing(emu.rom(), emu.load_address()), "coverage");
+        save_report(&options.lcov, &coverage.lcov(emu.rom(), emu.load_address(), &options.rom), "coverage");
+    }
+
+    println!("Shutting down!");
+}
+
+fn save_report(path: &Option<PathBuf>, text: &str, what: &str) {
+    if let Some(path) = path {
+        match std::fs::write(path, text) {
+            Ok(()) => println!("Saved {} to {}", what, path.display()),
+            Err(e) => eprintln!("Could not save {} {}: {}", what, path.display(), e),
+        }
+    }
+}
+
+// Fill in the settings the ROM database has for the loaded ROM, unless they
+// were given on the command line or changed in the config file
+fn configure_for_rom(options: &mut Options, sha1: &str) {
+    let mut database = Database::bundled();
+    if let Some(ref path) = options.database {
+        database.load_file(path).unwrap_or_else(|e| fail(&e));
+    }
+
+    if let Some(program) = database.lookup(sha1) {
+        println!("Found {} in the ROM database", program.title);
+
+        match program.timing {
+            Some(timing) if !options.explicit.timing => options.timing = timing,
+            _ => {}
+        }
+        match program.quirks {
+            Some(quirks) if !options.explicit.quirks => options.quirks = quirks,
+            _ => {}
+        }
+        match program.palette {
+            Some(palette) if !options.explicit.palette => options.palette = palette,
+            _ => {}
+        }
+
+        program.apply_key_hints(&mut options.keymap);
+    }
+
+    // Keymaps can have sections for particular ROMs, and have the last word
+    // on keys
+    if let Some(ref path) = options.keymap_file {
+        options.keymap.apply_file(path, &options.rom).unwrap_or_else(|e| fail(&e));
+    }
+}
+
+fn run<F: Frontend>(emu: &mut Chip8, frontend: &mut F, console: &mut Console, options: &Options) {
+    let mut recorder = options.record.as_ref().and_then(|target| start_recording(target, options, frontend));
+    let mut watcher = if options.watch { Some(Watcher::new(&options.rom)) } else { None };
+    let mut watch_frames = 0;
+    let commands = if options.cheat_console { Some(cheats::stdin_lines()) } else { None };
+    let mut pacer = options.pacer.clone();
+    let mut status = String::new();
+    // Emulated frames and instructions since the counters were last updated
+    let (mut stats_start, mut stats_frames, mut stats_instructions) = (Instant::now(), 0, emu.instruction_count());
+
+    'running: loop {
+        let frame_start = Instant::now();
+
+        for action in frontend.handle_events(emu.keyboard_mut()) {
+            match action {
+                Action::Quit => break 'running,
+                Action::Screenshot => {
+                    let path = screenshot::file_name(
+                        &options.screenshot_dir, &options.rom, options.screenshot_format.extension());
+
+                    match screenshot::save(emu.framebuffer(), &options.palette, SCREENSHOT_SCALE, &path) {
+                        Ok(()) => {
+                            frontend.log(&format!("Screenshot saved to {}", path.display()));
+                            frontend.show_message("Screenshot saved");
+                        }
+                        Err(e) => {
+                            frontend.log_error(&format!("Could not save screenshot {}: {}", path.display(), e));
+                            frontend.show_message("Screenshot failed");
+                        }
+                    }
+                }
+                Action::TogglePause => pacer.toggle_pause(),
+                Action::FrameAdvance => pacer.advance_frame(),
+                Action::FastForward(on) => pacer.set_fast_forward(on),
+                Action::ToggleSlowMotion => pacer.toggle_slow_motion(),
+                // Changing memory under a running program is rarely useful
+                Action::Poke(address, value) => {
+                    if pacer.is_paused() {
+                        emu.poke(address, value);
+                    } else {
+                        frontend.show_message("Pause to edit memory");
+                    }
+                }
+                Action::SoftReset => {
+                    emu.soft_reset();
+                    frontend.show_message("Soft reset");
+                }
+                Action::HardReset => match emu.hard_reset() {
+                    Ok(()) => {
+                        console.cheats().apply(emu);
+                        frontend.show_message("Hard reset");
+                    }
+                    Err(e) => {
+                        frontend.log_error(&format!("Could not reset: {}", e));
+                        frontend.show_message("Reset failed");
+                    }
+                }
+                Action::ToggleRecording => match recorder.take() {
+                    Some(recording) => stop_recording(recording, frontend),
+                    None => {
+                        // Recordings started from the keyboard get a new file
+                        // each time, a pipe is started again
+                        let target = match options.record {
+                            Some(Recording::Pipe(ref command)) => Recording::Pipe(command.clone()),
+                            _ => Recording::Gif(screenshot::file_name(
+                                &options.recording_dir, &options.rom, "gif")),
+                        };
+                        recorder = start_recording(&target, options, frontend);
+                    }
+                }
+            }
+        }
+
+        watch_frames = (watch_frames + 1) % WATCH_FRAMES;
+        if let Some(ref mut watcher) = watcher {
+            if watch_frames == 0 && watcher.changed() {
+                // A failed reload leaves the old ROM running, e.g. when the
+                // assembler only wrote half the file so far
+                match rom::read(&options.rom).and_then(|rom| emu.load_rom_bytes(&rom)) {
+                    Ok(()) => {
+                        console.cheats().apply(emu);
+                        frontend.log(&format!("Reloaded {}", options.rom));
+                        frontend.show_message("ROM reloaded");
+                    }
+                    Err(e) => {
+                        frontend.log_error(&format!("Could not reload: {}", e));
+                        frontend.show_message("Reload failed");
+                    }
+                }
+            }
+        }
+
+        if let Some(ref commands) = commands {
+            for line in commands.try_iter() {
+                match console.execute(&line, emu) {
+                    Ok(output) if output.is_empty() => {}
+                    Ok(output) => frontend.log(&output),
+                    Err(e) => frontend.log_error(&e),
+                }
+            }
+        }
+
+        // Uncapped fast forward runs frames until it's time to show one
+        let frames = pacer.frames();
+        let mut ran = 0;
+        loop {
+            let done = match frames {
+                Some(frames) => ran >= frames,
+                None => ran > 0 && frame_start.elapsed() >= FRAME_TIME,
+            };
+            if done {
+                break;
+            }
+
+            console.cheats().freeze(emu);
+            emu.frame();
+            ran += 1;
+
+            if let Some(ref mut recording) = recorder {
+                if let Err(e) = recording.capture(emu.framebuffer()) {
+                    frontend.log_error(&format!("Recording failed: {}", e));
+                    frontend.show_message("Recording failed");
+                    recorder = None;
+                }
+            }
+        }
+
+        stats_frames += ran;
+        let elapsed = stats_start.elapsed();
+        if elapsed >= STATS_TIME {
+            let seconds = elapsed.as_secs_f32();
+            let instructions = emu.instruction_count() - stats_instructions;
+            frontend.set_stats(stats_frames as f32 / seconds, instructions as f32 / seconds);
+
+            stats_start = Instant::now();
+            stats_frames = 0;
+            stats_instructions = emu.instruction_count();
+        }
+
+        if emu.take_draw_flag() || frontend.needs_redraw() {
+            frontend.draw(emu.framebuffer());
+        }
+        frontend.show_memory(emu.memory(), emu.pc(), emu.index());
+        frontend.beep(emu.sound_playing() && !pacer.is_paused());
+
+        if pacer.status() != status {
+            status = pacer.status();
+            frontend.set_status(&status);
+        }
+
+        std::thread::sleep(FRAME_TIME.saturating_sub(frame_start.elapsed()));
+    }
+
+    if let Some(recording) = recorder {
+        stop_recording(recording, frontend);
+    }
+}
+
+fn start_recording<F: Frontend>(target: &Recording, options: &Options, frontend: &mut F) -> Option<Recorder> {
+    let (recorder, name) = match target {
+        Recording::Gif(path) => (
+            Recorder::gif(path, &options.palette, RECORDING_SCALE),
+            path.display().to_string()
+        ),
+        Recording::Pipe(command) => (
+            Recorder::pipe(command, &options.palette, RECORDING_SCALE),
+            command.clone()
+        ),
+    };
+
+    match recorder {
+        Ok(recorder) => {
+            frontend.log(&format!("Recording to {}", name));
+            frontend.show_message("Recording");
+            Some(recorder)
+        }
+        Err(e) => {
+            frontend.log_error(&format!("Could not record to {}: {}", name, e));
+            frontend.show_message("Recording failed");
+            None
+        }
+    }
+}
+
+fn stop_recording<F: Frontend>(recorder: Recorder, frontend: &mut F) {
+    match recorder.finish() {
+        Ok(()) => {
+            frontend.log("Recording stopped");
+            frontend.show_message("Recording stopped");
+        }
+        Err(e) => {
+            frontend.log_error(&format!("Could not finish recording: {}", e));
+            frontend.show_message("Recording failed");
+        }
+    }
+}
+
+fn parse_args() -> Options {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().map(String::as_str) == Some("analyze") {
+        analyze(&args[1..]);
+        std::process::exit(0);
+    }
+
+    // `chip8 config [OPTIONS]` prints the config with the options applied
+    let show_config = args.first().map(String::as_str) == Some("config");
+    if show_config {
+        args.remove(0);
+    }
+
+    let mut config = Config::load().unwrap_or_else(|e| fail(&e));
+    let mut rom = String::from("roms/tetris.rom");
+    let mut tty = if cfg!(feature = "sdl") { None } else { Some(TtyMode::HalfBlock) };
+    let mut record = None;
+    let mut watch = false;
+    let mut cheat_console = false;
+    let mut profile = None;
+    let mut profile_folded = None;
+    let mut coverage = None;
+    let mut lcov = None;
+    // The config file only counts where it changes a default, so one
+    // written out by `chip8 config` doesn't turn the database off
+    let defaults = Config::default();
+    let mut explicit = Explicit {
+        timing: config.timing != defaults.timing || config.ips != defaults.ips,
+        quirks: config.quirks != defaults.quirks,
+        palette: config.palette != defaults.palette,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--vip" => {
+                config.timing = String::from("vip");
+                explicit.timing = true;
+            }
+            "--ips" => {
+                config.ips = match args.next().and_then(|n| n.parse().ok()) {
+                    Some(ips) if ips <= MAX_IPS => ips,
+                    _ => usage()
+                };
+                config.timing = String::from("fixed");
+                explicit.timing = true;
+            }
+            "--quirks" => {
+                config.quirks = args.next().unwrap_or_else(|| usage());
+                explicit.quirks = true;
+            }
+            "--palette" => {
+                config.palette = args.next().unwrap_or_else(|| usage());
+                explicit.palette = true;
+            }
+            "--load-address" => {
+                config.load_address = match args.next().as_deref().and_then(parse_address) {
+                    Some(address) => address,
+                    None => usage()
+                };
+            }
+            "--keymap" => config.keymap = Some(args.next().unwrap_or_else(|| usage())),
+            "--database" => config.database = Some(args.next().unwrap_or_else(|| usage())),
+            #[cfg(feature = "sdl")]
+            "--scale" => {
+                config.scale = match args.next().and_then(|n| n.parse().ok()) {
+                    Some(scale) => scale,
+                    None => usage()
+                };
+            }
+            #[cfg(feature = "sdl")]
+            "--filter" => config.filter = args.next().unwrap_or_else(|| usage()),
+            #[cfg(feature = "sdl")]
+            "--scaling" => config.scaling = args.next().unwrap_or_else(|| usage()),
+            #[cfg(not(feature = "sdl"))]
+            "--scale" | "--filter" | "--scaling" => fail(&format!("{} is not available without the sdl feature", arg)),
+            "--watch" => watch = true,
+            "--cheats" => cheat_console = true,
+            "--profile" => profile = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--profile-folded" => profile_folded = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--coverage" => coverage = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--lcov" => lcov = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--cheat-dir" => config.cheat_dir = Some(args.next().unwrap_or_else(|| usage())),
+            "--tty" => tty = Some(TtyMode::HalfBlock),
+            "--braille" => tty = Some(TtyMode::Braille),
+            "--screenshot-dir" => config.screenshot_dir = args.next().unwrap_or_else(|| usage()),
+            "--screenshot-format" => config.screenshot_format = args.next().unwrap_or_else(|| usage()),
+            "--record" => {
+                record = match args.next() {
+                    Some(path) => Some(Recording::Gif(PathBuf::from(path))),
+                    None => usage()
+                };
+            }
+            "--record-pipe" => {
+                record = match args.next() {
+                    Some(command) => Some(Recording::Pipe(command)),
+                    None => usage()
+                };
+            }
+            _ => rom = arg,
+        }
+    }
+
+    if watch && rom == "-" {
+        fail("can't watch a ROM read from stdin");
+    }
+    if cheat_console && (rom == "-" || tty.is_some()) {
+        fail("the cheat console reads stdin, it can't be used with --tty or a ROM from stdin");
+    }
+
+    if show_config {
+        if let Some(path) = Config::path() {
+            println!("# {}", path.display());
+        }
+        print!("{}", config.to_toml());
+        std::process::exit(0);
+    }
+
+    let mut pacer = Pacer::new();
+    pacer.set_fast_forward_speed(if config.fast_forward > 0.0 { Some(config.fast_forward) } else { None });
+    pacer.set_slow_motion_speed(config.slow_motion);
+
+    Options {
+        rom,
+        load_address: config.load_address,
+        timing: match config.timing.as_str() {
+            "vip" => Timing::Vip,
+            "fixed" => Timing::PerSecond(config.ips.max(1)),
+            other => fail(&format!("invalid timing {}, expected fixed or vip", other)),
+        },
+        quirks: match config.quirks.as_str() {
+            "vip" => Quirks::vip(),
+            "modern" => Quirks::modern(),
+            other => fail(&format!("invalid quirks {}, expected vip or modern", other)),
+        },
+        palette: Palette::from_name_or_file(&config.palette).unwrap_or_else(|e| fail(&e)),
+        keymap: Keymap::default(),
+        keymap_file: config.keymap.clone(),
+        database: config.database.clone(),
+        explicit,
+        #[cfg(feature = "sdl")]
+        filter: Filter::from_name(&config.filter)
+            .unwrap_or_else(|| fail(&format!("invalid filter {}", config.filter))),
+        #[cfg(feature = "sdl")]
+        scaling: match config.scaling.as_str() {
+            "integer" => Scaling::Integer,
+            "fractional" => Scaling::Fractional,
+            other => fail(&format!("invalid scaling {}, expected integer or fractional", other)),
+        },
+        #[cfg(feature = "sdl")]
+        scale: config.scale.max(1),
+        #[cfg(feature = "sdl")]
+        audio: config.audio.clone(),
+        tty,
+        screenshot_dir: PathBuf::from(&config.screenshot_dir),
+        screenshot_format: Format::from_name(&config.screenshot_format)
+            .unwrap_or_else(|| fail(&format!("invalid screenshot format {}", config.screenshot_format))),
+        recording_dir: PathBuf::from(config.recording_dir),
+        record,
+        watch,
+        pacer,
+        cheat_dir: config.cheat_dir.map(PathBuf::from)
+            .or_else(cheats::default_dir)
+            .unwrap_or_else(|| PathBuf::from("cheats")),
+        cheat_console,
+        profile,
+        profile_folded,
+        coverage,
+        lcov,
+    }
+}
+
+// `chip8 analyze ROM` prints a disassembly of the ROM's reachable code, and
+// can write its control flow and call graphs as Graphviz DOT files
+fn analyze(args: &[String]) {
+    let mut rom = None;
+    let mut load_address = Config::load().unwrap_or_else(|e| fail(&e)).load_address;
+    let mut cfg = None;
+    let mut call_graph = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--load-address" => {
+                load_address = match args.next().and_then(|address| parse_address(address)) {
+                    Some(address) => address,
+                    None => usage()
+                };
+            }
+            "--cfg" => cfg = Some(args.next().unwrap_or_else(|| usage())),
+            "--call-graph" => call_graph = Some(args.next().unwrap_or_else(|| usage())),
+            _ => rom = Some(arg),
+        }
+    }
+
+    let rom = rom.unwrap_or_else(|| usage());
+    let analysis = Analysis::new(&rom::read(rom).unwrap_or_else(|e| fail(&e)), load_address);
+
+    print!("{}", analysis.listing());
+    eprint!("{}", analysis.summary());
+
+    for (path, dot) in [(cfg, analysis.cfg_dot()), (call_graph, analysis.call_graph_dot())] {
+        if let Some(path) = path {
+            std::fs::write(path, dot).unwrap_or_else(|e| fail(&format!("could not write {}: {}", path, e)));
+        }
+    }
+}
+
+// An address a program can start at in hex like 0x600, or decimal
+fn parse_address(text: &str) -> Option<u16> {
+    let address = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
+    address.filter(|&address| address <= MAX_LOAD_ADDRESS)
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+fn usage() -> ! {
+    eprintln!("usage: chip8 [config] [ROM|-] [--vip] [--ips N] [--quirks vip|modern] [--palette NAME|FILE]\n             [--load-address ADDR] [--watch] [--keymap FILE] [--database FILE]\n             [--filter none|decay|deflicker|blend] [--scale N] [--scaling integer|fractional]\n             [--tty|--braille] [--screenshot-dir DIR] [--screenshot-format png|ppm|pbm]\n             [--record FILE.gif|--record-pipe COMMAND] [--cheats] [--cheat-dir DIR]\n             [--profile FILE] [--profile-folded FILE] [--coverage FILE] [--lcov FILE]");
+    eprintln!("       chip8 analyze ROM [--load-address ADDR] [--cfg FILE.dot] [--call-graph FILE.dot]");
+    std::process::exit(1);
 }
@@ -0,0 +1,240 @@
+// What the memory viewer shows: a hex dump of the 4 KiB of memory with a
+// cursor for poking bytes. Drawing it is up to the frontend.
+
+use crate::chip8::FONT_SIZE;
+
+pub const BYTES_PER_ROW: usize = 16;
+pub const ROWS: usize = 4096 / BYTES_PER_ROW;
+
+// Why a byte stands out, the first that applies wins
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Mark {
+    Plain,
+    Font,
+    // Written by the last frame that wrote anything
+    Changed,
+    // The index register points here
+    Index,
+    // The next instruction, both of its bytes
+    Pc,
+    Cursor,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<(u8, Mark)>,
+}
+
+pub struct MemoryView {
+    memory: [u8; 4096],
+    changed: [bool; 4096],
+    // Nothing to compare against before the first update
+    seen: bool,
+    pc: u16,
+    index: u16,
+    cursor: u16,
+    // The first row shown and how many fit
+    top: usize,
+    visible: usize,
+    // The high nibble of a byte being typed in
+    pending: Option<u8>,
+}
+
+impl MemoryView {
+    pub fn new(visible: usize) -> MemoryView {
+        MemoryView {
+            memory: [0; 4096],
+            changed: [false; 4096],
+            seen: false,
+            pc: 0,
+            index: 0,
+            cursor: 0,
+            top: 0,
+            visible: visible.clamp(1, ROWS),
+            pending: None,
+        }
+    }
+
+    // Take in the emulator's state. Changed bytes stay marked until memory
+    // changes again, so they don't vanish as soon as the game is paused.
+    // Returns whether anything in it changed.
+    pub fn update(&mut self, memory: &[u8; 4096], pc: u16, index: u16) -> bool {
+        let changed = !self.seen || *memory != self.memory || pc != self.pc || index != self.index;
+
+        if self.seen && *memory != self.memory {
+            for (address, changed) in self.changed.iter_mut().enumerate() {
+                *changed = memory[address] != self.memory[address];
+            }
+        }
+
+        self.memory = *memory;
+        self.seen = true;
+        self.pc = pc;
+        self.index = index;
+
+        changed
+    }
+
+    pub fn cursor(&self) -> u16 {
+        self.cursor
+    }
+
+    // The first digit of the byte being typed at the cursor
+    pub fn pending(&self) -> Option<u8> {
+        self.pending
+    }
+
+    pub fn move_cursor(&mut self, offset: i32) {
+        let cursor = (self.cursor as i32 + offset).clamp(0, 4095);
+        self.goto(cursor as u16);
+    }
+
+    // Put the cursor on an address, scrolling it into view
+    pub fn goto(&mut self, address: u16) {
+        self.cursor = address & 0xFFF;
+        self.pending = None;
+
+        let row = self.cursor as usize / BYTES_PER_ROW;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + self.visible {
+            self.top = row + 1 - self.visible;
+        }
+    }
+
+    pub fn goto_pc(&mut self) {
+        self.goto(self.pc);
+    }
+
+    pub fn goto_index(&mut self) {
+        self.goto(self.index);
+    }
+
+    // Type a hex digit at the cursor. The second digit of a byte gives the
+    // address and value to poke, and moves on to the next byte.
+    pub fn type_digit(&mut self, digit: u8) -> Option<(u16, u8)> {
+        match self.pending.take() {
+            None => {
+                self.pending = Some(digit & 0xF);
+                None
+            }
+            Some(high) => {
+                let poke = (self.cursor, high << 4 | (digit & 0xF));
+                if self.cursor < 4095 {
+                    self.goto(self.cursor + 1);
+                }
+                Some(poke)
+            }
+        }
+    }
+
+    // The rows in view
+    pub fn lines(&self) -> Vec<Line> {
+        (self.top..self.top + self.visible)
+            .map(|row| {
+                let start = row * BYTES_PER_ROW;
+                let bytes = (start..start + BYTES_PER_ROW)
+                    .map(|address| (self.memory[address], self.mark(address)))
+                    .collect();
+
+                Line { address: start as u16, bytes }
+            })
+            .collect()
+    }
+
+    fn mark(&self, address: usize) -> Mark {
+        let pc = self.pc as usize;
+
+        if address == self.cursor as usize {
+            Mark::Cursor
+        } else if address == pc || address == pc + 1 {
+            Mark::Pc
+        } else if address == self.index as usize {
+            Mark::Index
+        } else if self.changed[address] {
+            Mark::Changed
+        } else if address < FONT_SIZE {
+            Mark::Font
+        } else {
+            Mark::Plain
+        }
+    }
+}
+
+
+#[test]
+fn test_marks() {
+    let mut memory = [0; 4096];
+    let mut view = MemoryView::new(ROWS);
+    view.update(&memory, 0x200, 0x300);
+    view.goto(0x10);
+
+    memory[0x400] = 0xAB;
+    view.update(&memory, 0x200, 0x300);
+
+    let lines = view.lines();
+    assert_eq!(lines.len(), ROWS);
+    assert_eq!(lines[0].bytes[0], (0, Mark::Font));
+    assert_eq!(lines[1].bytes[0], (0, Mark::Cursor));
+    assert_eq!(lines[0x20].bytes[0].1, Mark::Pc);
+    assert_eq!(lines[0x20].bytes[1].1, Mark::Pc);
+    assert_eq!(lines[0x30].bytes[0].1, Mark::Index);
+    assert_eq!(lines[0x40].bytes[0], (0xAB, Mark::Changed));
+    assert_eq!(lines[0x40].bytes[1], (0, Mark::Plain));
+
+    // Still marked while nothing changes, e.g. when paused
+    view.update(&memory, 0x202, 0x300);
+    assert_eq!(view.lines()[0x40].bytes[0].1, Mark::Changed);
+
+    memory[0x401] = 1;
+    view.update(&memory, 0x202, 0x300);
+    assert_eq!(view.lines()[0x40].bytes[0].1, Mark::Plain);
+}
+
+#[test]
+fn test_first_update_marks_nothing() {
+    let mut view = MemoryView::new(ROWS);
+    assert!(view.update(&[0xFF; 4096], 0, 0));
+    assert!(!view.update(&[0xFF; 4096], 0, 0));
+
+    assert!(view.lines().iter().all(|line| line.bytes.iter().all(|&(_, mark)| mark != Mark::Changed)));
+}
+
+#[test]
+fn test_scrolling() {
+    let mut view = MemoryView::new(4);
+    assert_eq!(view.lines()[0].address, 0);
+
+    view.move_cursor(16 * 4);
+    assert_eq!(view.lines()[0].address, 0x10);
+    assert_eq!(view.lines()[3].address, 0x40);
+
+    view.move_cursor(-16 * 2);
+    assert_eq!(view.lines()[0].address, 0x10);
+    view.move_cursor(-16);
+    assert_eq!(view.lines()[0].address, 0x10);
+    view.move_cursor(-16);
+    assert_eq!(view.lines()[0].address, 0);
+
+    view.move_cursor(10000);
+    assert_eq!(view.cursor(), 0xFFF);
+    assert_eq!(view.lines()[3].address, 0xFF0);
+}
+
+#[test]
+fn test_type_digits() {
+    let mut view = MemoryView::new(ROWS);
+    view.goto(0x200);
+
+    assert_eq!(view.type_digit(0xA), None);
+    assert_eq!(view.pending(), Some(0xA));
+    assert_eq!(view.type_digit(0x2), Some((0x200, 0xA2)));
+    assert_eq!(view.cursor(), 0x201);
+
+    // Moving away drops a half typed byte
+    view.type_digit(0x1);
+    view.move_cursor(1);
+    assert_eq!(view.pending(), None);
+    assert_eq!(view.type_digit(0x1), None);
+}
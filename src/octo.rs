@@ -0,0 +1,847 @@
+// A compiler for Octo, the assembly language most modern CHIP-8 programs are
+// written in. Only what a plain CHIP-8 runs is supported, programs for the
+// SUPER-CHIP and XO-CHIP extensions are turned down.
+
+use std::collections::HashMap;
+
+use crate::rom::{DEFAULT_LOAD_ADDRESS, MEMORY_SIZE};
+
+// Catches macros that expand into themselves
+const MAX_EXPANSIONS: usize = 100_000;
+
+const BINARY_OPERATORS: [&str; 18] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", ">", "<=", ">=", "==",
+];
+
+const EXTENSIONS: [&str; 16] = [
+    "hires", "lores", "scroll-down", "scroll-up", "scroll-left", "scroll-right", "exit", "saveflags",
+    "loadflags", "plane", "audio", "pitch", "bighex", "long", ":stringmode", ":pointer",
+];
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+// Label addresses that weren't known yet when they were used
+enum Fixup {
+    // The low 12 bits of an instruction
+    Address,
+    // The two instructions of :unpack, with the nibble it was given
+    Unpack(u8),
+}
+
+// The open `begin`, `else` and `loop` blocks, with the jumps to patch when
+// they are closed
+enum Control {
+    Begin(usize),
+    Else(usize),
+    Loop(u16, Vec<usize>),
+}
+
+struct Compiler {
+    // What is left to compile, in reverse so macros can push their bodies
+    tokens: Vec<Token>,
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, (Vec<String>, Vec<Token>)>,
+    expansions: usize,
+    fixups: Vec<(usize, String, Fixup, usize)>,
+    control: Vec<Control>,
+}
+
+// Compile Octo source into a program to load at 0x200. Errors say which
+// line they are on.
+pub fn compile(source: &str) -> Result<Vec<u8>, String> {
+    let mut compiler = Compiler::new(source);
+    compiler.compile().map_err(|e| format!("line {}: {}", compiler.line, e))?;
+
+    // Programs start with a jump to main, unless it comes first
+    let main = *compiler.labels.get("main").ok_or("the program has no main")?;
+    if main as usize != DEFAULT_LOAD_ADDRESS as usize {
+        if compiler.rom.len() < 2 {
+            compiler.rom.resize(2, 0);
+        }
+        compiler.rom[0] = 0x10 | (main >> 8) as u8;
+        compiler.rom[1] = main as u8;
+    }
+
+    Ok(compiler.rom)
+}
+
+impl Compiler {
+    fn new(source: &str) -> Compiler {
+        let mut tokens: Vec<Token> = source.lines()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                let code = line.split('#').next().unwrap_or("");
+                code.split_whitespace().map(move |text| Token { text: text.to_string(), line: i + 1 })
+            })
+            .collect();
+        tokens.reverse();
+
+        Compiler {
+            tokens,
+            line: 1,
+            rom: Vec::new(),
+            // Room for the jump to main
+            here: DEFAULT_LOAD_ADDRESS as usize + 2,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            fixups: Vec::new(),
+            control: Vec::new(),
+        }
+    }
+
+    fn compile(&mut self) -> Result<(), String> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+
+        match self.control.last() {
+            Some(Control::Begin(_)) | Some(Control::Else(_)) => return Err(String::from("a begin is missing its end")),
+            Some(Control::Loop(..)) => return Err(String::from("a loop is missing its again")),
+            None => {}
+        }
+
+        for (offset, name, fixup, line) in std::mem::take(&mut self.fixups) {
+            self.line = line;
+            let address = *self.labels.get(&name).ok_or(format!("{} is not defined", name))?;
+            match fixup {
+                Fixup::Address => self.patch(offset, address),
+                Fixup::Unpack(nibble) => {
+                    self.rom[offset + 1] = nibble << 4 | (address >> 8) as u8;
+                    self.rom[offset + 3] = address as u8;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                // A main right at the start needs no jump to it
+                if name == "main" && self.here == DEFAULT_LOAD_ADDRESS as usize + 2 && self.rom.is_empty() {
+                    self.here = DEFAULT_LOAD_ADDRESS as usize;
+                }
+                self.labels.insert(name, self.here as u16);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let value = self.value(&token)?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut parameters = Vec::new();
+                loop {
+                    match self.next()? {
+                        brace if brace == "{" => break,
+                        parameter => parameters.push(parameter),
+                    }
+                }
+                let body = self.block()?;
+                self.macros.insert(name, (parameters, body));
+            }
+            ":org" => {
+                let token = self.next()?;
+                let address = self.address_value(&token)?;
+                if address < DEFAULT_LOAD_ADDRESS {
+                    return Err(format!("{} is below 0x200", token));
+                }
+                self.here = address as usize;
+            }
+            ":byte" => {
+                let (value, text) = if self.peek() == Some("{") {
+                    self.next()?;
+                    (self.calc()?, String::from("the expression"))
+                } else {
+                    let token = self.next()?;
+                    (self.value(&token)?, token)
+                };
+                let byte = to_byte(value, &text)?;
+                self.emit(byte)?;
+            }
+            ":unpack" => {
+                let token = self.next()?;
+                let nibble = self.value(&token)? as i64;
+                if !(0..=0xF).contains(&nibble) {
+                    return Err(format!("{} doesn't fit in a nibble", token));
+                }
+                let label = self.next()?;
+                let offset = self.offset();
+                let address = match self.labels.get(&label) {
+                    Some(&address) => address,
+                    None => {
+                        self.fixups.push((offset, label, Fixup::Unpack(nibble as u8), self.line));
+                        0
+                    }
+                };
+                self.instruction(0x6000 | (nibble as u16) << 4 | address >> 8)?;
+                self.instruction(0x6100 | address & 0xFF)?;
+            }
+            ":call" => self.address_instruction(0x2000)?,
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "clear" => self.instruction(0x00E0)?,
+            "return" | ";" => self.instruction(0x00EE)?,
+            "jump" => self.address_instruction(0x1000)?,
+            "jump0" => self.address_instruction(0xB000)?,
+            "native" => self.address_instruction(0x0000)?,
+            "bcd" => self.register_instruction(0xF033)?,
+            "save" => self.register_instruction(0xF055)?,
+            "load" => self.register_instruction(0xF065)?,
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let token = self.next()?;
+                let rows = self.value(&token)? as i64;
+                if !(0..=15).contains(&rows) {
+                    return Err(format!("sprites are 0 to 15 rows high, not {}", token));
+                }
+                self.instruction(0xD000 | x << 8 | y << 4 | rows as u16)?;
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let opcode = if token == "delay" { 0xF015 } else { 0xF018 };
+                self.register_instruction(opcode)?;
+            }
+            "i" => self.index()?,
+            "if" => {
+                let mut instructions = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => {
+                        for instruction in instructions {
+                            self.instruction(instruction)?;
+                        }
+                    }
+                    "begin" => {
+                        let offset = self.skip_unless(&mut instructions)?;
+                        self.control.push(Control::Begin(offset));
+                    }
+                    other => return Err(format!("expected then or begin, found {}", other)),
+                }
+            }
+            "else" => match self.control.pop() {
+                Some(Control::Begin(offset)) => {
+                    let jump = self.offset();
+                    self.instruction(0x1000)?;
+                    self.patch(offset, self.here as u16);
+                    self.control.push(Control::Else(jump));
+                }
+                _ => return Err(String::from("else without begin")),
+            },
+            "end" => match self.control.pop() {
+                Some(Control::Begin(offset)) | Some(Control::Else(offset)) => self.patch(offset, self.here as u16),
+                _ => return Err(String::from("end without begin")),
+            },
+            "loop" => self.control.push(Control::Loop(self.here as u16, Vec::new())),
+            "while" => {
+                let mut instructions = self.condition()?;
+                if !self.control.iter().any(|control| matches!(control, Control::Loop(..))) {
+                    return Err(String::from("while outside of a loop"));
+                }
+                let offset = self.skip_unless(&mut instructions)?;
+                if let Some(Control::Loop(_, exits)) = self.control.iter_mut().rev().find(|control| matches!(control, Control::Loop(..))) {
+                    exits.push(offset);
+                }
+            }
+            "again" => match self.control.pop() {
+                Some(Control::Loop(start, exits)) => {
+                    self.instruction(0x1000 | start)?;
+                    for offset in exits {
+                        self.patch(offset, self.here as u16);
+                    }
+                }
+                _ => return Err(String::from("again without loop")),
+            },
+            _ if EXTENSIONS.contains(&token.as_str()) => {
+                return Err(format!("{} is for SUPER-CHIP or XO-CHIP, only CHIP-8 is supported", token));
+            }
+            _ => {
+                if let Some(x) = self.register_of(&token) {
+                    self.register_statement(x)?;
+                } else if self.macros.contains_key(&token) {
+                    self.expand(&token)?;
+                } else if let Some(value) = self.value_of(&token) {
+                    let byte = to_byte(value, &token)?;
+                    self.emit(byte)?;
+                } else if is_name(&token) {
+                    // Calling a subroutine is just its name
+                    self.address(0x2000, token)?;
+                } else {
+                    return Err(format!("unexpected {}", token));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), String> {
+        let x16 = (x as u16) << 8;
+        let operator = self.next()?;
+        let token = self.next()?;
+        let y = self.register_of(&token).map(|y| x16 | (y as u16) << 4);
+
+        let instruction = match (operator.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | y,
+            (":=", None) => match token.as_str() {
+                "random" => {
+                    let token = self.next()?;
+                    0xC000 | x16 | self.byte(&token)? as u16
+                }
+                "delay" => 0xF007 | x16,
+                "key" => 0xF00A | x16,
+                _ => 0x6000 | x16 | self.byte(&token)? as u16,
+            },
+            ("+=", Some(y)) => 0x8004 | y,
+            ("+=", None) => 0x7000 | x16 | self.byte(&token)? as u16,
+            ("-=", Some(y)) => 0x8005 | y,
+            ("-=", None) => 0x7000 | x16 | self.byte(&token)?.wrapping_neg() as u16,
+            ("=-", Some(y)) => 0x8007 | y,
+            ("|=", Some(y)) => 0x8001 | y,
+            ("&=", Some(y)) => 0x8002 | y,
+            ("^=", Some(y)) => 0x8003 | y,
+            (">>=", Some(y)) => 0x8006 | y,
+            ("<<=", Some(y)) => 0x800E | y,
+            ("=-", None) | ("|=", None) | ("&=", None) | ("^=", None) | (">>=", None) | ("<<=", None) => {
+                return Err(format!("expected a register after {}, found {}", operator, token));
+            }
+            _ => return Err(format!("unknown operator {}", operator)),
+        };
+
+        self.instruction(instruction)
+    }
+
+    fn index(&mut self) -> Result<(), String> {
+        let operator = self.next()?;
+        match operator.as_str() {
+            ":=" => {
+                let token = self.next()?;
+                match token.as_str() {
+                    "hex" => self.register_instruction(0xF029),
+                    "bighex" | "long" => Err(format!("{} is for SUPER-CHIP or XO-CHIP, only CHIP-8 is supported", token)),
+                    _ => self.address(0xA000, token),
+                }
+            }
+            "+=" => self.register_instruction(0xF01E),
+            _ => Err(format!("unknown operator {}", operator)),
+        }
+    }
+
+    // The instructions to test a condition, the last one skips what follows
+    // when the condition is false. Comparisons other than == and != go
+    // through vF.
+    fn condition(&mut self) -> Result<Vec<u16>, String> {
+        let x = self.register()? as u16;
+        let operator = self.next()?;
+
+        match operator.as_str() {
+            "key" => return Ok(vec![0xE0A1 | x << 8]),
+            "-key" => return Ok(vec![0xE09E | x << 8]),
+            _ => {}
+        }
+
+        let token = self.next()?;
+        let (y, byte) = match self.register_of(&token) {
+            Some(y) => (Some(y as u16), 0),
+            None => (None, self.byte(&token)? as u16),
+        };
+
+        let instructions = match (operator.as_str(), y) {
+            ("==", Some(y)) => vec![0x9000 | x << 8 | y << 4],
+            ("==", None) => vec![0x4000 | x << 8 | byte],
+            ("!=", Some(y)) => vec![0x5000 | x << 8 | y << 4],
+            ("!=", None) => vec![0x3000 | x << 8 | byte],
+            (">", _) | ("<", _) | (">=", _) | ("<=", _) => {
+                let load = match y {
+                    Some(y) => 0x8F00 | y << 4,
+                    None => 0x6F00 | byte,
+                };
+                // vF ends up 1 when y >= x for > and <=, and when x >= y for
+                // < and >=
+                let (subtract, flag) = match operator.as_str() {
+                    ">" => (0x8F05, 0),
+                    "<" => (0x8F07, 0),
+                    ">=" => (0x8F07, 1),
+                    _ => (0x8F05, 1),
+                };
+                vec![load, subtract | x << 4, 0x4F00 | flag]
+            }
+            _ => return Err(format!("unknown comparison {}", operator)),
+        };
+
+        Ok(instructions)
+    }
+
+    // Jump away when the condition is false, returns where the jump is so it
+    // can be pointed at the end of the block
+    fn skip_unless(&mut self, instructions: &mut [u16]) -> Result<usize, String> {
+        let last = instructions.len() - 1;
+        instructions[last] = negate(instructions[last]);
+        for &instruction in instructions.iter() {
+            self.instruction(instruction)?;
+        }
+
+        let offset = self.offset();
+        self.instruction(0x1000)?;
+        Ok(offset)
+    }
+
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!("too many macro expansions, does {} expand into itself?", name));
+        }
+
+        let (parameters, body) = self.macros[name].clone();
+        let mut arguments = Vec::new();
+        for _ in &parameters {
+            arguments.push(self.next()?);
+        }
+
+        for token in body.into_iter().rev() {
+            let text = match parameters.iter().position(|parameter| *parameter == token.text) {
+                Some(i) => arguments[i].clone(),
+                None => token.text,
+            };
+            self.tokens.push(Token { text, line: self.line });
+        }
+
+        Ok(())
+    }
+
+    // The tokens up to the closing brace, after the opening one
+    fn block(&mut self) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+
+        loop {
+            let token = self.tokens.pop().ok_or("a { is missing its }")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(tokens),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+
+    // Expressions in braces are worked out right to left, without operator
+    // precedence, like Octo does
+    fn calc(&mut self) -> Result<f64, String> {
+        let tokens = self.block()?;
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position)?;
+
+        match tokens.get(position) {
+            Some(token) => Err(format!("unexpected {} in expression", token.text)),
+            None => Ok(value),
+        }
+    }
+
+    fn expression(&self, tokens: &[Token], position: &mut usize) -> Result<f64, String> {
+        let left = self.term(tokens, position)?;
+
+        match tokens.get(*position) {
+            Some(token) if BINARY_OPERATORS.contains(&token.text.as_str()) => {
+                *position += 1;
+                let right = self.expression(tokens, position)?;
+                Ok(binary(&token.text, left, right))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn term(&self, tokens: &[Token], position: &mut usize) -> Result<f64, String> {
+        let token = tokens.get(*position).ok_or("the expression ends early")?;
+        *position += 1;
+
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, position)?;
+                match tokens.get(*position) {
+                    Some(token) if token.text == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => Err(String::from("a ( is missing its )")),
+                }
+            }
+            "-" => Ok(-self.term(tokens, position)?),
+            "~" => Ok(!(self.term(tokens, position)? as i64) as f64),
+            "!" => Ok((self.term(tokens, position)? == 0.0) as u8 as f64),
+            "HERE" => Ok(self.here as f64),
+            text => self.value_of(text)
+                .or_else(|| self.labels.get(text).map(|&address| address as f64))
+                .ok_or(format!("{} is not defined", text)),
+        }
+    }
+
+    // Instructions taking an address accept labels, which may come later
+    fn address_instruction(&mut self, opcode: u16) -> Result<(), String> {
+        let token = self.next()?;
+        self.address(opcode, token)
+    }
+
+    fn address(&mut self, opcode: u16, token: String) -> Result<(), String> {
+        let address = match self.labels.get(&token) {
+            Some(&address) => address,
+            None if self.value_of(&token).is_some() => self.address_value(&token)?,
+            None if is_name(&token) && self.register_of(&token).is_none() => {
+                self.fixups.push((self.offset(), token, Fixup::Address, self.line));
+                0
+            }
+            None => return Err(format!("expected an address, found {}", token)),
+        };
+
+        self.instruction(opcode | address)
+    }
+
+    fn address_value(&self, token: &str) -> Result<u16, String> {
+        let address = self.value(token)? as i64;
+        if !(0..MEMORY_SIZE as i64).contains(&address) {
+            return Err(format!("{} is not an address", token));
+        }
+        Ok(address as u16)
+    }
+
+    fn register_instruction(&mut self, opcode: u16) -> Result<(), String> {
+        let x = self.register()? as u16;
+        self.instruction(opcode | x << 8)
+    }
+
+    fn instruction(&mut self, instruction: u16) -> Result<(), String> {
+        self.emit((instruction >> 8) as u8)?;
+        self.emit(instruction as u8)
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), String> {
+        if self.here >= MEMORY_SIZE {
+            return Err(String::from("the program doesn't fit in memory"));
+        }
+
+        let offset = self.offset();
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    // Where the next byte goes in the ROM
+    fn offset(&self) -> usize {
+        self.here - DEFAULT_LOAD_ADDRESS as usize
+    }
+
+    // Point the jump at offset to an address
+    fn patch(&mut self, offset: usize, address: u16) {
+        self.rom[offset] = self.rom[offset] & 0xF0 | (address >> 8) as u8;
+        self.rom[offset + 1] = address as u8;
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.pop().ok_or("unexpected end of file")?;
+        self.line = token.line;
+        Ok(token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != text {
+            return Err(format!("expected {}, found {}", text, token));
+        }
+        Ok(())
+    }
+
+    // A new name for a label, constant, alias or macro
+    fn name(&mut self) -> Result<String, String> {
+        let token = self.next()?;
+        if !is_name(&token) || self.register_of(&token).is_some() {
+            return Err(format!("{} can't be used as a name", token));
+        }
+        if self.labels.contains_key(&token) || self.constants.contains_key(&token) || self.macros.contains_key(&token) {
+            return Err(format!("{} is already defined", token));
+        }
+        Ok(token)
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.register_of(&token).ok_or(format!("expected a register, found {}", token))
+    }
+
+    // v0 to vF, or an alias of one
+    fn register_of(&self, token: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+
+        let mut chars = token.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => digit.to_digit(16).map(|x| x as u8),
+            _ => None,
+        }
+    }
+
+    // A number or a constant
+    fn value_of(&self, token: &str) -> Option<f64> {
+        self.constants.get(token).copied().or_else(|| parse_number(token).map(|value| value as f64))
+    }
+
+    fn value(&self, token: &str) -> Result<f64, String> {
+        self.value_of(token).ok_or(format!("expected a number, found {}", token))
+    }
+
+    fn byte(&self, token: &str) -> Result<u8, String> {
+        to_byte(self.value(token)?, token)
+    }
+}
+
+// Bytes can be given as -128 to 255
+fn to_byte(value: f64, token: &str) -> Result<u8, String> {
+    let value = value.floor() as i64;
+    if !(-128..=255).contains(&value) {
+        return Err(format!("{} doesn't fit in a byte", token));
+    }
+    Ok(value as u8)
+}
+
+// Decimal, 0x hexadecimal or 0b binary, all possibly negative
+fn parse_number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn is_name(token: &str) -> bool {
+    let mut chars = token.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Turn a skip into the opposite one
+fn negate(instruction: u16) -> u16 {
+    match instruction >> 12 {
+        0x3 => instruction ^ 0x7000,
+        0x4 => instruction ^ 0x7000,
+        0x5 => instruction ^ 0xC000,
+        0x9 => instruction ^ 0xC000,
+        // SKP and SKNP
+        _ => instruction ^ 0x003F,
+    }
+}
+
+fn binary(operator: &str, left: f64, right: f64) -> f64 {
+    let (a, b) = (left as i64, right as i64);
+
+    match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        "&" => (a & b) as f64,
+        "|" => (a | b) as f64,
+        "^" => (a ^ b) as f64,
+        "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+        ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+        "pow" => left.powf(right),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "<" => (left < right) as u8 as f64,
+        ">" => (left > right) as u8 as f64,
+        "<=" => (left <= right) as u8 as f64,
+        ">=" => (left >= right) as u8 as f64,
+        _ => (left == right) as u8 as f64,
+    }
+}
+
+
+#[cfg(test)]
+fn words(source: &str) -> Vec<u16> {
+    compile(source).unwrap()
+        .chunks(2)
+        .map(|pair| (pair[0] as u16) << 8 | pair[1] as u16)
+        .collect()
+}
+
+#[test]
+fn test_instructions() {
+    let source = "
+        : main
+            clear
+            v0 := 5  v1 := v0  v2 := random 0x0F  v3 := delay  v4 := key
+            v0 += 1  v0 += v1  v0 -= 1  v0 -= v1  v0 =- v1
+            v0 |= v1  v0 &= v1  v0 ^= v1  v0 >>= v1  v0 <<= v1
+            i := 0x300  i += v2  i := hex v3
+            delay := v1  buzzer := v2
+            bcd v0  save v5  load vF
+            sprite v0 v1 15
+            jump0 0x400
+            return
+    ";
+
+    assert_eq!(words(source), [
+        0x00E0,
+        0x6005, 0x8100, 0xC20F, 0xF307, 0xF40A,
+        0x7001, 0x8014, 0x70FF, 0x8015, 0x8017,
+        0x8011, 0x8012, 0x8013, 0x8016, 0x801E,
+        0xA300, 0xF21E, 0xF329,
+        0xF115, 0xF218,
+        0xF033, 0xF555, 0xFF65,
+        0xD01F,
+        0xB400,
+        0x00EE,
+    ]);
+}
+
+#[test]
+fn test_main_and_labels() {
+    // Calls and jumps to labels that come later, and a jump to main since
+    // it isn't first
+    let source = "
+        : draw  i := ball  sprite v0 v0 1  ;
+        : main  draw  jump main
+        : ball  0b11110000
+    ";
+
+    assert_eq!(compile(source).unwrap(), [
+        0x12, 0x08,
+        0xA2, 0x0C, 0xD0, 0x01, 0x00, 0xEE,
+        0x22, 0x02, 0x12, 0x08,
+        0xF0,
+    ]);
+}
+
+#[test]
+fn test_control_flow() {
+    let source = "
+        : main
+            if v0 == 1 then v1 := 2
+            if v0 key begin
+                v1 := 3
+            else
+                v1 := 4
+            end
+            loop
+                v0 += 1
+                while v0 != 10
+            again
+    ";
+
+    assert_eq!(words(source), [
+        0x4001, 0x6102,             // 200
+        0xE09E, 0x120C, 0x6103,     // 204: skip the jump to else when pressed
+        0x120E, 0x6104,             // 20A: jump past else
+        0x7001, 0x400A, 0x1216,     // 20E: leave the loop once v0 is 10
+        0x120E,                     // 214
+    ]);
+}
+
+#[test]
+fn test_comparisons() {
+    use crate::chip8::Chip8;
+
+    // Stores 1 or 0 for every comparison, run on the emulator to check the
+    // vF tricks they compile to
+    let mut source = String::from(": main\n");
+    let mut address = 0x300;
+    for &(x, y) in &[(3, 5), (5, 5), (5, 3)] {
+        for comparison in &["<", ">", "<=", ">="] {
+            source += &format!("v0 := 0 v1 := {} v2 := {} if v1 {} v2 then v0 := 1 i := {} save v0\n", x, y, comparison, address);
+            address += 1;
+        }
+    }
+    source += "loop again\n";
+
+    let mut emu = Chip8::new();
+    emu.load_rom_bytes(&compile(&source).unwrap()).unwrap();
+    for _ in 0..200 {
+        emu.tick();
+    }
+
+    assert_eq!(emu.memory()[0x300..0x30C], [
+        1, 0, 1, 0, // 3 vs 5
+        0, 0, 1, 1, // 5 vs 5
+        0, 1, 0, 1, // 5 vs 3
+    ]);
+}
+
+#[test]
+fn test_alias_const_calc_macro() {
+    let source = "
+        :alias score v7
+        :const SPEED 3
+        :calc WIDTH { 8 * 2 + SPEED }
+        :macro move reg amount { reg += amount }
+        : main
+            score := WIDTH
+            move score SPEED
+            :byte { 0x30 >> 4 }
+            -1
+            :unpack 0xA data
+        : data
+    ";
+
+    assert_eq!(compile(source).unwrap(), [0x67, 0x28, 0x77, 0x03, 0x03, 0xFF, 0x60, 0xA2, 0x61, 0x0A]);
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(compile(": main\n  v0 := 300"), Err(String::from("line 2: 300 doesn't fit in a byte")));
+    assert_eq!(compile(": main\n\n  jump nowhere"), Err(String::from("line 3: nowhere is not defined")));
+    assert_eq!(compile(": main loop"), Err(String::from("line 1: a loop is missing its again")));
+    assert_eq!(compile(": main hires"), Err(String::from("line 1: hires is for SUPER-CHIP or XO-CHIP, only CHIP-8 is supported")));
+    assert_eq!(compile(": start ;"), Err(String::from("the program has no main")));
+}
@@ -1,6 +1,8 @@
+use std::fmt;
+
 use super::util::{concat_12, concat_8};
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Opcode {
     SYS(u16),
@@ -93,6 +95,50 @@ impl Opcode {
     }
 }
 
+// Disassembled in the usual mnemonics, e.g. `LD V1, 0x05`
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Opcode::SYS(addr) => write!(f, "SYS 0x{:03X}", addr),
+            Opcode::CLS => write!(f, "CLS"),
+            Opcode::RET => write!(f, "RET"),
+            Opcode::JP(addr) => write!(f, "JP 0x{:03X}", addr),
+            Opcode::CALL(addr) => write!(f, "CALL 0x{:03X}", addr),
+            Opcode::SE(x, byte) => write!(f, "SE V{:X}, 0x{:02X}", x, byte),
+            Opcode::SNE(x, byte) => write!(f, "SNE V{:X}, 0x{:02X}", x, byte),
+            Opcode::SE_V(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Opcode::LD(x, byte) => write!(f, "LD V{:X}, 0x{:02X}", x, byte),
+            Opcode::ADD(x, byte) => write!(f, "ADD V{:X}, 0x{:02X}", x, byte),
+            Opcode::LD_V(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Opcode::OR(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Opcode::AND(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Opcode::XOR(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Opcode::ADD_V(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Opcode::SUB(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Opcode::SHR(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Opcode::SUBN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Opcode::SHL(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Opcode::SNE_V(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Opcode::LD_I(addr) => write!(f, "LD I, 0x{:03X}", addr),
+            Opcode::JP_V(addr) => write!(f, "JP V0, 0x{:03X}", addr),
+            Opcode::RND(x, byte) => write!(f, "RND V{:X}, 0x{:02X}", x, byte),
+            Opcode::DRW(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Opcode::SKP(x) => write!(f, "SKP V{:X}", x),
+            Opcode::SKNP(x) => write!(f, "SKNP V{:X}", x),
+            Opcode::LD_V_DT(x) => write!(f, "LD V{:X}, DT", x),
+            Opcode::LD_K(x) => write!(f, "LD V{:X}, K", x),
+            Opcode::LD_DT_V(x) => write!(f, "LD DT, V{:X}", x),
+            Opcode::LD_ST(x) => write!(f, "LD ST, V{:X}", x),
+            Opcode::ADD_I(x) => write!(f, "ADD I, V{:X}", x),
+            Opcode::LD_F(x) => write!(f, "LD F, V{:X}", x),
+            Opcode::LD_B(x) => write!(f, "LD B, V{:X}", x),
+            Opcode::LD_I_V(x) => write!(f, "LD [I], V{:X}", x),
+            Opcode::LD_V_I(x) => write!(f, "LD V{:X}, [I]", x),
+            Opcode::UNDEFINED => write!(f, "???"),
+        }
+    }
+}
+
 #[test]
 fn test_nonexistant_opcode() {
     let code = 0xFFFF;
@@ -127,4 +173,12 @@ fn test_triplet_variable_opcode() {
 fn test_single_double_variable_opcode() {
     let code = 0x72FE;
     assert_eq!(Opcode::from_bytes(code), Opcode::ADD(0x2, 0xFE));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_display() {
+    assert_eq!(Opcode::from_bytes(0x6105).to_string(), "LD V1, 0x05");
+    assert_eq!(Opcode::from_bytes(0xD12F).to_string(), "DRW V1, V2, 15");
+    assert_eq!(Opcode::from_bytes(0xFA55).to_string(), "LD [I], VA");
+    assert_eq!(Opcode::from_bytes(0xB300).to_string(), "JP V0, 0x300");
+}
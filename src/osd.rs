@@ -0,0 +1,282 @@
+// An on-screen display drawn over the game: messages, speed counters and the
+// keypad. Everything is laid out in OSD pixels, which the frontend scales.
+
+// How long messages stay up, in host frames
+const MESSAGE_FRAMES: u32 = 120;
+const MAX_MESSAGES: usize = 4;
+
+// Glyphs are 3x5 pixels, with a pixel of space between them
+pub const GLYPH_WIDTH: i32 = 3;
+pub const GLYPH_HEIGHT: i32 = 5;
+const ADVANCE: i32 = GLYPH_WIDTH + 1;
+// Labels are drawn on a box one pixel bigger than their text all round
+const LINE_HEIGHT: i32 = GLYPH_HEIGHT + 3;
+const MARGIN: i32 = 2;
+
+// The keypad as it's laid out on the COSMAC VIP
+const KEYPAD: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// Text on a box, the frontend decides the colours
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    // Top left corner of the text
+    pub x: i32,
+    pub y: i32,
+    pub text: String,
+    // Drawn the other way round, e.g. for keys being held
+    pub highlight: bool,
+}
+
+pub struct Osd {
+    messages: Vec<(String, u32)>,
+    show_stats: bool,
+    show_keypad: bool,
+    fps: f32,
+    ips: f32,
+    status: String,
+    keys: [bool; 16],
+}
+
+impl Osd {
+    pub fn new() -> Osd {
+        Osd {
+            messages: Vec::new(),
+            show_stats: false,
+            show_keypad: false,
+            fps: 0.0,
+            ips: 0.0,
+            status: String::new(),
+            keys: [false; 16],
+        }
+    }
+
+    // Show a message for a couple of seconds
+    pub fn message(&mut self, text: &str) {
+        self.messages.push((text.to_string(), MESSAGE_FRAMES));
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+    }
+
+    // Advance one host frame, letting old messages go
+    pub fn tick(&mut self) {
+        for (_, frames) in self.messages.iter_mut() {
+            *frames -= 1;
+        }
+        self.messages.retain(|&(_, frames)| frames > 0);
+    }
+
+    pub fn set_stats(&mut self, fps: f32, ips: f32) {
+        self.fps = fps;
+        self.ips = ips;
+    }
+
+    // The emulation speed, empty when it's normal
+    pub fn set_status(&mut self, status: &str) {
+        self.status = status.to_string();
+    }
+
+    pub fn set_keys(&mut self, keys: &[bool; 16]) {
+        self.keys = *keys;
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.show_stats = !self.show_stats;
+    }
+
+    pub fn toggle_keypad(&mut self) {
+        self.show_keypad = !self.show_keypad;
+    }
+
+    // Whether there's anything to draw
+    pub fn is_visible(&self) -> bool {
+        !self.messages.is_empty() || self.show_stats || self.show_keypad || !self.status.is_empty()
+    }
+
+    // Lay the OSD out on an area of the given size in OSD pixels: counters
+    // and speed top left, messages bottom left and the keypad bottom right
+    pub fn layout(&self, width: i32, height: i32) -> Vec<Label> {
+        let mut labels = Vec::new();
+        let label = |x, y, text: &str, highlight| Label { x, y, text: text.to_string(), highlight };
+
+        let mut y = MARGIN;
+        if self.show_stats {
+            let text = format!("{:.0} FPS {:.0} IPS", self.fps, self.ips);
+            labels.push(label(MARGIN, y, &text, false));
+            y += LINE_HEIGHT;
+        }
+        if !self.status.is_empty() {
+            labels.push(label(MARGIN, y, &self.status, false));
+        }
+
+        let mut y = height - MARGIN - GLYPH_HEIGHT;
+        for (text, _) in self.messages.iter().rev() {
+            labels.push(label(MARGIN, y, text, false));
+            y -= LINE_HEIGHT;
+        }
+
+        if self.show_keypad {
+            let left = width - MARGIN - 4 * LINE_HEIGHT + 2;
+            let top = height - MARGIN - 4 * LINE_HEIGHT + 2;
+            for (row, keys) in KEYPAD.iter().enumerate() {
+                for (col, &key) in keys.iter().enumerate() {
+                    let text = format!("{:X}", key);
+                    let (x, y) = (left + col as i32 * LINE_HEIGHT, top + row as i32 * LINE_HEIGHT);
+                    labels.push(label(x, y, &text, self.keys[key]));
+                }
+            }
+        }
+
+        labels
+    }
+}
+
+impl Default for Osd {
+    fn default() -> Osd {
+        Osd::new()
+    }
+}
+
+pub fn text_width(text: &str) -> i32 {
+    text.chars().count() as i32 * ADVANCE - 1
+}
+
+// The pixels lit by a piece of text, relative to its top left corner
+pub fn text_pixels(text: &str) -> Vec<(i32, i32)> {
+    let mut pixels = Vec::new();
+
+    for (i, c) in text.chars().enumerate() {
+        for (y, row) in glyph(c).iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if row & (0b100 >> x) != 0 {
+                    pixels.push((i as i32 * ADVANCE + x, y as i32));
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+// A 3x5 glyph, one row per byte with the leftmost pixel in bit 2. There are
+// only capitals, lowercase letters are drawn as capitals.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010], // ?
+    }
+}
+
+
+#[test]
+fn test_text_pixels() {
+    assert_eq!(text_pixels("1"), vec![(1, 0), (0, 1), (1, 1), (1, 2), (1, 3), (0, 4), (1, 4), (2, 4)]);
+    assert_eq!(text_pixels(" ."), vec![(ADVANCE + 1, 4)]);
+    assert_eq!(text_pixels("a"), text_pixels("A"));
+    assert_eq!(text_width("AB"), 7);
+}
+
+#[test]
+fn test_messages_expire() {
+    let mut osd = Osd::new();
+    assert!(!osd.is_visible());
+
+    osd.message("Screenshot saved");
+    for _ in 0..MESSAGE_FRAMES - 1 {
+        osd.tick();
+    }
+    assert!(osd.is_visible());
+
+    osd.tick();
+    assert!(!osd.is_visible());
+}
+
+#[test]
+fn test_layout() {
+    let mut osd = Osd::new();
+    osd.message("old");
+    osd.message("new");
+    osd.set_status("Paused");
+
+    let labels = osd.layout(200, 100);
+    assert_eq!(labels[0].text, "Paused");
+    assert_eq!(labels[0].y, MARGIN);
+    // Newest message at the bottom
+    assert_eq!(labels[1].text, "new");
+    assert_eq!(labels[1].y, 100 - MARGIN - GLYPH_HEIGHT);
+    assert_eq!(labels[2].text, "old");
+    assert!(labels[2].y < labels[1].y);
+}
+
+#[test]
+fn test_keypad() {
+    let mut osd = Osd::new();
+    osd.toggle_keypad();
+
+    let mut keys = [false; 16];
+    keys[0xC] = true;
+    osd.set_keys(&keys);
+
+    let labels = osd.layout(200, 100);
+    assert_eq!(labels.len(), 16);
+    assert_eq!(labels[3].text, "C");
+    assert!(labels[3].highlight);
+    assert!(labels.iter().filter(|label| label.highlight).count() == 1);
+    // Fits inside the area
+    assert!(labels.iter().all(|label| label.x + text_width(&label.text) < 200 && label.y + GLYPH_HEIGHT < 100));
+}
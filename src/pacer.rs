@@ -0,0 +1,180 @@
+// Decides how many emulated frames run each time the host shows a frame,
+// 60 times a second: none while paused, more while fast forwarding and
+// fewer in slow motion.
+
+// What the status is at normal speed, which the on-screen display leaves out
+pub const NORMAL_STATUS: &str = "100%";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pacer {
+    paused: bool,
+    // Run one frame even though paused
+    step: bool,
+    fast_forward: bool,
+    slow_motion: bool,
+    // How fast fast forward is, None runs as many frames as the host can
+    fast_speed: Option<f32>,
+    slow_speed: f32,
+    // Fractions of a frame carried over to the next host frame
+    owed: f32,
+}
+
+impl Pacer {
+    pub fn new() -> Pacer {
+        Pacer {
+            paused: false,
+            step: false,
+            fast_forward: false,
+            slow_motion: false,
+            fast_speed: None,
+            slow_speed: 0.25,
+            owed: 0.0,
+        }
+    }
+
+    // A multiple of normal speed, or None for as fast as possible
+    pub fn set_fast_forward_speed(&mut self, speed: Option<f32>) {
+        self.fast_speed = speed;
+    }
+
+    // A fraction of normal speed
+    pub fn set_slow_motion_speed(&mut self, speed: f32) {
+        self.slow_speed = speed;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.step = false;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.set_paused(!self.paused);
+    }
+
+    // Pause, then run a single frame
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.step = true;
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn toggle_slow_motion(&mut self) {
+        self.slow_motion = !self.slow_motion;
+    }
+
+    // How many emulated frames to run for this host frame, or None to run
+    // as many as fit into it
+    pub fn frames(&mut self) -> Option<u32> {
+        if self.paused {
+            return Some(std::mem::replace(&mut self.step, false) as u32);
+        }
+
+        let speed = match (self.fast_forward, self.fast_speed) {
+            (true, None) => return None,
+            (true, Some(speed)) => speed,
+            (false, _) if self.slow_motion => self.slow_speed,
+            (false, _) => 1.0,
+        };
+
+        self.owed += speed;
+        let frames = self.owed.floor();
+        self.owed -= frames;
+
+        Some(frames as u32)
+    }
+
+    // A short description of the speed for a title bar, like "100%" at
+    // normal speed
+    pub fn status(&self) -> String {
+        if self.paused {
+            String::from("Paused")
+        } else if self.fast_forward {
+            match self.fast_speed {
+                Some(speed) => format!("Fast forward {}", percent(speed)),
+                None => String::from("Fast forward"),
+            }
+        } else if self.slow_motion {
+            format!("Slow motion {}", percent(self.slow_speed))
+        } else {
+            String::from(NORMAL_STATUS)
+        }
+    }
+}
+
+fn percent(speed: f32) -> String {
+    format!("{}%", (speed * 100.0).round())
+}
+
+impl Default for Pacer {
+    fn default() -> Pacer {
+        Pacer::new()
+    }
+}
+
+
+#[test]
+fn test_normal_speed() {
+    let mut pacer = Pacer::new();
+
+    for _ in 0..10 {
+        assert_eq!(pacer.frames(), Some(1));
+    }
+    assert_eq!(pacer.status(), "100%");
+}
+
+#[test]
+fn test_pause_and_advance() {
+    let mut pacer = Pacer::new();
+    pacer.toggle_pause();
+    assert_eq!(pacer.frames(), Some(0));
+    assert_eq!(pacer.status(), "Paused");
+
+    pacer.advance_frame();
+    assert_eq!(pacer.frames(), Some(1));
+    assert_eq!(pacer.frames(), Some(0));
+
+    pacer.toggle_pause();
+    assert_eq!(pacer.frames(), Some(1));
+
+    // Advancing while running pauses
+    pacer.advance_frame();
+    assert!(pacer.is_paused());
+    assert_eq!(pacer.frames(), Some(1));
+    assert_eq!(pacer.frames(), Some(0));
+}
+
+#[test]
+fn test_fast_forward() {
+    let mut pacer = Pacer::new();
+    pacer.set_fast_forward(true);
+    assert_eq!(pacer.frames(), None);
+
+    pacer.set_fast_forward_speed(Some(3.0));
+    assert_eq!(pacer.frames(), Some(3));
+    assert_eq!(pacer.status(), "Fast forward 300%");
+
+    pacer.set_fast_forward(false);
+    assert_eq!(pacer.frames(), Some(1));
+}
+
+#[test]
+fn test_slow_motion() {
+    let mut pacer = Pacer::new();
+    pacer.toggle_slow_motion();
+
+    let frames: u32 = (0..8).map(|_| pacer.frames().unwrap()).sum();
+    assert_eq!(frames, 2);
+    assert_eq!(pacer.status(), "Slow motion 25%");
+
+    // Fast forward wins while it's held
+    pacer.set_fast_forward(true);
+    pacer.set_fast_forward_speed(Some(2.0));
+    assert_eq!(pacer.frames(), Some(2));
+}
@@ -0,0 +1,149 @@
+use std::fs;
+
+// Colours used to display the framebuffer, indexed by pixel value. Only the
+// first two are used by plain CHIP-8, the other two are for games that draw
+// on two bit planes, where 2 is the second plane and 3 is both overlapping.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Palette {
+    pub colors: [[u8; 3]; 4],
+}
+
+impl Palette {
+    pub fn classic() -> Palette {
+        Palette {
+            colors: [[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55]],
+        }
+    }
+
+    pub fn amber() -> Palette {
+        Palette {
+            colors: [[0x1A, 0x0F, 0x00], [0xFF, 0xB0, 0x00], [0xB3, 0x6B, 0x00], [0x66, 0x3D, 0x00]],
+        }
+    }
+
+    pub fn green() -> Palette {
+        Palette {
+            colors: [[0x00, 0x11, 0x00], [0x33, 0xFF, 0x33], [0x1F, 0xAA, 0x1F], [0x10, 0x55, 0x10]],
+        }
+    }
+
+    pub fn lcd() -> Palette {
+        Palette {
+            colors: [[0x9B, 0xBC, 0x0F], [0x0F, 0x38, 0x0F], [0x30, 0x62, 0x30], [0x8B, 0xAC, 0x0F]],
+        }
+    }
+
+    pub fn octo() -> Palette {
+        Palette {
+            colors: [[0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]],
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Palette> {
+        match name {
+            "classic" => Some(Palette::classic()),
+            "amber" => Some(Palette::amber()),
+            "green" => Some(Palette::green()),
+            "lcd" => Some(Palette::lcd()),
+            "octo" => Some(Palette::octo()),
+            _ => None
+        }
+    }
+
+    // Look up a preset by name, falling back to loading it as a palette file
+    pub fn from_name_or_file(name: &str) -> Result<Palette, String> {
+        match Palette::preset(name) {
+            Some(palette) => Ok(palette),
+            None => Palette::from_file(name),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Palette, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read palette {}: {}", path, e))?;
+
+        Palette::parse(&text)
+    }
+
+    // Parse a palette from a list of 2 or 4 hex colours like `#FFB000`,
+    // separated by whitespace or commas. With only 2 colours the plane
+    // colours are kept from the classic palette.
+    pub fn parse(text: &str) -> Result<Palette, String> {
+        let mut palette = Palette::classic();
+
+        let colors: Vec<&str> = text
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        if colors.len() != 2 && colors.len() != 4 {
+            return Err(format!("expected 2 or 4 colours, found {}", colors.len()));
+        }
+
+        for (i, color) in colors.iter().enumerate() {
+            palette.colors[i] = parse_color(color)?;
+        }
+
+        Ok(palette)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::classic()
+    }
+}
+
+fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let hex = color.trim_start_matches('#');
+    // Sliced by bytes below
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(format!("invalid colour {}", color));
+    }
+
+    let mut rgb = [0; 3];
+    for (i, channel) in rgb.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[i*2..i*2+2], 16)
+            .map_err(|_| format!("invalid colour {}", color))?;
+    }
+
+    Ok(rgb)
+}
+
+
+#[test]
+fn test_parse_color() {
+    assert_eq!(parse_color("#FFB000"), Ok([0xFF, 0xB0, 0x00]));
+    assert_eq!(parse_color("1a0f00"), Ok([0x1A, 0x0F, 0x00]));
+    assert!(parse_color("#FFB0").is_err());
+    assert!(parse_color("#GGGGGG").is_err());
+    assert!(parse_color("#aééa").is_err());
+}
+
+#[test]
+fn test_parse_two_colors() {
+    let palette = Palette::parse("#000000, #33FF33\n").unwrap();
+
+    assert_eq!(palette.colors[0], [0x00, 0x00, 0x00]);
+    assert_eq!(palette.colors[1], [0x33, 0xFF, 0x33]);
+    assert_eq!(palette.colors[2], Palette::classic().colors[2]);
+}
+
+#[test]
+fn test_parse_four_colors() {
+    let palette = Palette::parse("#996600 #FFCC00\n#FF6600 #662200").unwrap();
+
+    assert_eq!(palette, Palette::octo());
+}
+
+#[test]
+fn test_parse_wrong_count() {
+    assert!(Palette::parse("#000000").is_err());
+    assert!(Palette::parse("#000000 #111111 #222222").is_err());
+}
+
+#[test]
+fn test_preset() {
+    assert_eq!(Palette::preset("amber"), Some(Palette::amber()));
+    assert_eq!(Palette::preset("nope"), None);
+}
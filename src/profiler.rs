@@ -0,0 +1,245 @@
+// Counts where a program spends its time: instructions and VIP machine
+// cycles per address, per subroutine by following CALL and RET, and per
+// frame against what a COSMAC VIP can run in one.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::opcode::Opcode;
+use crate::timing::{VIP_CYCLES_PER_FRAME, VIP_INTERRUPT_CYCLES};
+
+// The CHIP-8 stack is 16 deep, deeper call chains are cut off
+const MAX_DEPTH: usize = 16;
+
+// What a VIP has left for the program each frame
+const FRAME_BUDGET: u64 = (VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES) as u64;
+
+pub struct Profiler {
+    counts: Vec<u64>,
+    cycles: Vec<u64>,
+    // The last instruction seen at each address, for the report
+    opcodes: Vec<Opcode>,
+    // Entries of the subroutines being run, outermost first
+    stack: Vec<u16>,
+    // Each call stack seen gets a number, the cycles spent in it are kept by
+    // that number so stacks aren't hashed for every instruction
+    stack_ids: HashMap<Vec<u16>, usize>,
+    stack_id: usize,
+    stack_cycles: Vec<u64>,
+    stack_counts: Vec<u64>,
+    frames: u64,
+    frame_cycles: u64,
+    most_frame_cycles: u64,
+    slow_frames: u64,
+}
+
+impl Profiler {
+    // Calls are followed from the program's entry point
+    pub fn new(entry: u16) -> Profiler {
+        let stack = vec![entry];
+        let mut stack_ids = HashMap::new();
+        stack_ids.insert(stack.clone(), 0);
+
+        Profiler {
+            counts: vec![0; 4096],
+            cycles: vec![0; 4096],
+            opcodes: vec![Opcode::UNDEFINED; 4096],
+            stack,
+            stack_ids,
+            stack_id: 0,
+            stack_cycles: vec![0],
+            stack_counts: vec![0],
+            frames: 0,
+            frame_cycles: 0,
+            most_frame_cycles: 0,
+            slow_frames: 0,
+        }
+    }
+
+    // Called before each instruction runs, with what it costs on the VIP
+    pub(crate) fn record(&mut self, pc: u16, opcode: Opcode, cycles: u32) {
+        let address = pc as usize & 0xFFF;
+        self.counts[address] += 1;
+        self.cycles[address] += cycles as u64;
+        self.opcodes[address] = opcode;
+        self.stack_counts[self.stack_id] += 1;
+        self.stack_cycles[self.stack_id] += cycles as u64;
+        self.frame_cycles += cycles as u64;
+
+        match opcode {
+            Opcode::CALL(target) if self.stack.len() < MAX_DEPTH => {
+                self.stack.push(target);
+                self.update_stack_id();
+            }
+            // The outermost entry stays, a RET there is the program misbehaving
+            Opcode::RET if self.stack.len() > 1 => {
+                self.stack.pop();
+                self.update_stack_id();
+            }
+            _ => {}
+        }
+    }
+
+    fn update_stack_id(&mut self) {
+        let next_id = self.stack_ids.len();
+        self.stack_id = *self.stack_ids.entry(self.stack.clone()).or_insert(next_id);
+
+        if self.stack_id == next_id {
+            self.stack_cycles.push(0);
+            self.stack_counts.push(0);
+        }
+    }
+
+    // Called after every 60Hz frame
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+        self.most_frame_cycles = self.most_frame_cycles.max(self.frame_cycles);
+        if self.frame_cycles > FRAME_BUDGET {
+            self.slow_frames += 1;
+        }
+        self.frame_cycles = 0;
+    }
+
+    // Subroutine entries with the instructions and cycles spent in them
+    // alone, and in them including what they called
+    fn subroutines(&self) -> Vec<(u16, [u64; 4])> {
+        let mut totals: HashMap<u16, [u64; 4]> = HashMap::new();
+
+        for (stack, &id) in &self.stack_ids {
+            let (count, cycles) = (self.stack_counts[id], self.stack_cycles[id]);
+
+            let own = totals.entry(*stack.last().unwrap()).or_default();
+            own[0] += count;
+            own[1] += cycles;
+
+            // Recursion would count twice
+            let mut seen = Vec::new();
+            for &entry in stack {
+                if !seen.contains(&entry) {
+                    seen.push(entry);
+                    let total = totals.entry(entry).or_default();
+                    total[2] += count;
+                    total[3] += cycles;
+                }
+            }
+        }
+
+        let mut subroutines: Vec<(u16, [u64; 4])> = totals.into_iter().collect();
+        subroutines.sort_by_key(|&(entry, totals)| (std::cmp::Reverse(totals[3]), entry));
+        subroutines
+    }
+
+    // Frame times, the busiest addresses and subroutines, sorted by VIP
+    // cycles
+    pub fn report(&self, limit: usize) -> String {
+        let mut text = String::new();
+        let total_count: u64 = self.counts.iter().sum();
+        let total_cycles: u64 = self.cycles.iter().sum();
+        let percent = |part: u64, total: u64| if total == 0 { 0.0 } else { part as f64 * 100.0 / total as f64 };
+
+        writeln!(text, "{} frames, {} instructions, {} VIP cycles", self.frames, total_count, total_cycles).unwrap();
+        if let Some(average) = total_cycles.checked_div(self.frames) {
+            writeln!(text, "VIP cycles per frame: {} on average, {} at most, {} available",
+                average, self.most_frame_cycles, FRAME_BUDGET).unwrap();
+            writeln!(text, "{} frames ({:.1}%) would be too slow on a VIP",
+                self.slow_frames, percent(self.slow_frames, self.frames)).unwrap();
+        }
+
+        let mut addresses: Vec<usize> = (0..4096).filter(|&address| self.counts[address] > 0).collect();
+        addresses.sort_by_key(|&address| (std::cmp::Reverse(self.cycles[address]), address));
+
+        writeln!(text, "\nAddress       Count      %        Cycles      %  Instruction").unwrap();
+        for &address in addresses.iter().take(limit) {
+            writeln!(text, "0x{:03X}  {:>12} {:>5.1}% {:>13} {:>5.1}%  {}",
+                address,
+                self.counts[address], percent(self.counts[address], total_count),
+                self.cycles[address], percent(self.cycles[address], total_cycles),
+                self.opcodes[address]).unwrap();
+        }
+
+        writeln!(text, "\nSubroutine   Self cycles      %   Total cycles      %  Instructions").unwrap();
+        for (entry, totals) in self.subroutines().into_iter().take(limit) {
+            writeln!(text, "sub_{:03X}  {:>15} {:>5.1}% {:>14} {:>5.1}%  {}",
+                entry,
+                totals[1], percent(totals[1], total_cycles),
+                totals[3], percent(totals[3], total_cycles),
+                totals[0]).unwrap();
+        }
+
+        text
+    }
+
+    // Call stacks and the VIP cycles spent in them, one per line like
+    // `sub_200;sub_2B6 1234`, for flamegraph.pl and compatible tools
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self.stack_ids.iter()
+            .filter(|&(_, &id)| self.stack_cycles[id] > 0)
+            .map(|(stack, &id)| {
+                let names: Vec<String> = stack.iter().map(|entry| format!("sub_{:03X}", entry)).collect();
+                format!("{} {}", names.join(";"), self.stack_cycles[id])
+            })
+            .collect();
+
+        lines.sort();
+        lines.iter().map(|line| line.clone() + "\n").collect()
+    }
+}
+
+
+#[cfg(test)]
+fn run(profiler: &mut Profiler, program: &[(u16, u16, u32)]) {
+    for &(pc, bytes, cycles) in program {
+        profiler.record(pc, Opcode::from_bytes(bytes), cycles);
+    }
+}
+
+#[test]
+fn test_counts() {
+    let mut profiler = Profiler::new(0x200);
+    run(&mut profiler, &[(0x200, 0x6000, 10), (0x202, 0x1202, 20), (0x202, 0x1202, 20)]);
+    profiler.end_frame();
+
+    let report = profiler.report(10);
+    assert!(report.starts_with("1 frames, 3 instructions, 50 VIP cycles\n"));
+    // Busiest first
+    assert!(report.contains("0x202             2  66.7%            40  80.0%  JP 0x202\n0x200"));
+}
+
+#[test]
+fn test_call_stacks() {
+    let mut profiler = Profiler::new(0x200);
+    run(&mut profiler, &[
+        (0x200, 0x2300, 30), // CALL 0x300
+        (0x300, 0x2400, 30), // CALL 0x400
+        (0x400, 0x6000, 5),
+        (0x402, 0x00EE, 10), // RET
+        (0x302, 0x00EE, 10), // RET
+        (0x202, 0x1202, 12),
+    ]);
+
+    assert_eq!(profiler.folded(), "sub_200 42\nsub_200;sub_300 40\nsub_200;sub_300;sub_400 15\n");
+
+    let subroutines = profiler.subroutines();
+    assert_eq!(subroutines[0], (0x200, [2, 42, 6, 97]));
+    assert_eq!(subroutines[1], (0x300, [2, 40, 4, 55]));
+    assert_eq!(subroutines[2], (0x400, [2, 15, 2, 15]));
+}
+
+#[test]
+fn test_slow_frames() {
+    let mut profiler = Profiler::new(0x200);
+    profiler.record(0x200, Opcode::CLS, FRAME_BUDGET as u32 + 1);
+    profiler.end_frame();
+    profiler.record(0x200, Opcode::CLS, 100);
+    profiler.end_frame();
+
+    assert!(profiler.report(10).contains("1 frames (50.0%) would be too slow on a VIP"));
+}
+
+#[test]
+fn test_unbalanced_ret() {
+    let mut profiler = Profiler::new(0x200);
+    run(&mut profiler, &[(0x200, 0x00EE, 10), (0x202, 0x6000, 5)]);
+
+    assert_eq!(profiler.folded(), "sub_200 15\n");
+}
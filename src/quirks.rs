@@ -0,0 +1,41 @@
+// Behaviours that differ between CHIP-8 interpreters. Games are usually
+// written against one of them, so these can be toggled per game.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Quirks {
+    // DRW waits for the next 60Hz frame before the CPU continues
+    pub display_wait: bool,
+    // Sprite pixels past the right edge are dropped instead of wrapping
+    pub clip_x: bool,
+    // Sprite pixels past the bottom edge are dropped instead of wrapping
+    pub clip_y: bool,
+}
+
+impl Quirks {
+    // The original COSMAC VIP interpreter
+    pub fn vip() -> Quirks {
+        Quirks {
+            display_wait: true,
+            clip_x: true,
+            clip_y: true,
+        }
+    }
+
+    // What most modern interpreters do
+    pub fn modern() -> Quirks {
+        Quirks {
+            display_wait: false,
+            clip_x: false,
+            clip_y: false,
+        }
+    }
+
+    pub fn profiles() -> [Quirks; 2] {
+        [Quirks::vip(), Quirks::modern()]
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::modern()
+    }
+}
@@ -0,0 +1,234 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+
+use crate::palette::Palette;
+use crate::screenshot;
+
+// Browsers and most viewers treat GIF delays below 2/100ths of a second as
+// much longer ones, so that's the shortest delay a frame gets.
+const MIN_DELAY: u64 = 2;
+
+enum Sink {
+    Gif(gif::Encoder<BufWriter<File>>),
+    // Raw RGB frames written to the stdin of an external encoder
+    Pipe(Child),
+}
+
+// Captures every frame the emulator presents, 60 per second
+pub struct Recorder {
+    sink: Sink,
+    palette: Palette,
+    scale: usize,
+    dedup: Dedup,
+}
+
+impl Recorder {
+    // Record to an animated GIF, identical frames are merged into one
+    pub fn gif(path: &Path, palette: &Palette, scale: usize) -> io::Result<Recorder> {
+        let file = BufWriter::new(File::create(path)?);
+        let global: Vec<u8> = palette.colors.iter().flatten().cloned().collect();
+
+        let mut encoder = gif::Encoder::new(file, 64 * scale as u16, 32 * scale as u16, &global)
+            .map_err(io::Error::other)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+
+        Ok(Recorder {
+            sink: Sink::Gif(encoder),
+            palette: *palette,
+            scale,
+            dedup: Dedup::new(),
+        })
+    }
+
+    // Pipe raw 24-bit RGB frames at a constant 60 fps into a shell command,
+    // e.g. `ffmpeg -f rawvideo -pix_fmt rgb24 -s 640x320 -r 60 -i - out.mp4`
+    pub fn pipe(command: &str, palette: &Palette, scale: usize) -> io::Result<Recorder> {
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .spawn()?;
+
+        Ok(Recorder {
+            sink: Sink::Pipe(child),
+            palette: *palette,
+            scale,
+            dedup: Dedup::new(),
+        })
+    }
+
+    // Call once per frame with the current framebuffer
+    pub fn capture(&mut self, screen: &[[u8; 64]; 32]) -> io::Result<()> {
+        match self.sink {
+            Sink::Gif(ref mut encoder) => {
+                if let Some((frame, delay)) = self.dedup.push(screen) {
+                    write_gif_frame(encoder, &frame, delay, self.scale)?;
+                }
+            }
+            Sink::Pipe(ref mut child) => {
+                let rgb = screenshot::to_rgb(screen, &self.palette, self.scale);
+                child.stdin.as_mut().unwrap().write_all(&rgb)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self.sink {
+            Sink::Gif(mut encoder) => {
+                let mut dedup = self.dedup;
+                if let Some((frame, delay)) = dedup.flush() {
+                    write_gif_frame(&mut encoder, &frame, delay, self.scale)?;
+                }
+
+                encoder.into_inner().map_err(io::Error::other)?.flush()
+            }
+            Sink::Pipe(mut child) => {
+                // Closing stdin tells the encoder the video is over
+                drop(child.stdin.take());
+                child.wait()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn write_gif_frame(
+    encoder: &mut gif::Encoder<BufWriter<File>>,
+    screen: &[[u8; 64]; 32],
+    delay: u64,
+    scale: usize,
+) -> io::Result<()> {
+    let mut pixels = Vec::with_capacity(64 * 32 * scale * scale);
+    for row in screen.iter() {
+        for _ in 0..scale {
+            for &pixel in row.iter() {
+                for _ in 0..scale {
+                    pixels.push(pixel & 0b11);
+                }
+            }
+        }
+    }
+
+    let mut frame = gif::Frame::from_indexed_pixels(64 * scale as u16, 32 * scale as u16, pixels, None);
+    frame.delay = delay.min(u16::MAX as u64) as u16;
+
+    encoder.write_frame(&frame).map_err(io::Error::other)
+}
+
+// Merges runs of identical frames into one frame with a longer delay, in
+// hundredths of a second. Delays are worked out from the total time so far
+// so rounding doesn't drift, and a frame that would be shown for less than
+// MIN_DELAY is replaced by the next one, which gets its time.
+struct Dedup {
+    pending: Option<[[u8; 64]; 32]>,
+    frames: u64,
+    emitted: u64,
+}
+
+impl Dedup {
+    fn new() -> Dedup {
+        Dedup { pending: None, frames: 0, emitted: 0 }
+    }
+
+    // Add a frame, returns the previous frame once it's known how long it lasts
+    fn push(&mut self, screen: &[[u8; 64]; 32]) -> Option<([[u8; 64]; 32], u64)> {
+        let out = match self.pending {
+            Some(pending) if pending == *screen => None,
+            Some(_) if self.elapsed() < MIN_DELAY => {
+                self.pending = None;
+                None
+            }
+            _ => self.flush(),
+        };
+
+        if self.pending.is_none() {
+            self.pending = Some(*screen);
+        }
+        self.frames += 1;
+
+        out
+    }
+
+    fn flush(&mut self) -> Option<([[u8; 64]; 32], u64)> {
+        let pending = self.pending.take()?;
+
+        // Only the last frame can be this short
+        let delay = self.elapsed().max(MIN_DELAY);
+        self.emitted += delay;
+
+        Some((pending, delay))
+    }
+
+    // Time since the pending frame started being shown
+    fn elapsed(&self) -> u64 {
+        let end = (self.frames * 100 + 30) / 60;
+        end.saturating_sub(self.emitted)
+    }
+}
+
+
+#[cfg(test)]
+fn frame_with(value: u8) -> [[u8; 64]; 32] {
+    let mut frame = [[0; 64]; 32];
+    frame[0][0] = value;
+    frame
+}
+
+#[test]
+fn test_dedup_merges_identical_frames() {
+    let mut dedup = Dedup::new();
+
+    for _ in 0..60 {
+        assert_eq!(dedup.push(&frame_with(1)), None);
+    }
+
+    let (frame, delay) = dedup.push(&frame_with(0)).unwrap();
+    assert_eq!(frame, frame_with(1));
+    assert_eq!(delay, 100);
+
+    for _ in 0..29 {
+        assert_eq!(dedup.push(&frame_with(0)), None);
+    }
+
+    let (frame, delay) = dedup.flush().unwrap();
+    assert_eq!(frame, frame_with(0));
+    assert_eq!(delay, 50);
+    assert_eq!(dedup.flush(), None);
+}
+
+#[test]
+fn test_dedup_minimum_delay() {
+    let mut dedup = Dedup::new();
+
+    // Alternating frames change faster than a GIF can show, some are
+    // dropped but a second still lasts a second
+    let mut delays = Vec::new();
+    for i in 0..60 {
+        delays.extend(dedup.push(&frame_with(i % 2)).map(|(_, delay)| delay));
+    }
+    delays.extend(dedup.flush().map(|(_, delay)| delay));
+
+    assert!(delays.iter().all(|&delay| delay >= MIN_DELAY));
+    assert_eq!(delays.iter().sum::<u64>(), 100);
+}
+
+#[test]
+fn test_gif_recording() {
+    let path = std::env::temp_dir().join("chip8-test-recording.gif");
+
+    let mut recorder = Recorder::gif(&path, &Palette::classic(), 1).unwrap();
+    for i in 0..10 {
+        recorder.capture(&frame_with(i / 5)).unwrap();
+    }
+    recorder.finish().unwrap();
+
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(&data[..6], b"GIF89a");
+    assert_eq!(data.last(), Some(&0x3B));
+}
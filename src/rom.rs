@@ -0,0 +1,162 @@
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::octo;
+
+// Where programs are loaded unless the platform says otherwise
+pub const DEFAULT_LOAD_ADDRESS: u16 = 0x200;
+// Programs for the ETI-660 start higher up
+pub const ETI_660_LOAD_ADDRESS: u16 = 0x600;
+// Programs have to start somewhere in the 4 KiB of memory
+pub const MEMORY_SIZE: usize = 4096;
+// The highest a program can start, with room left for one instruction
+pub const MAX_LOAD_ADDRESS: u16 = MEMORY_SIZE as u16 - 2;
+
+// Read a ROM from a file, from stdin if the path is `-`, from the one ROM
+// in a .zip archive, or compiled from Octo source in a .8o file
+pub fn read(path: &str) -> Result<Vec<u8>, String> {
+    let data = if path == "-" {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data)
+            .map_err(|e| format!("could not read ROM from stdin: {}", e))?;
+        data
+    } else {
+        fs::read(path).map_err(|e| format!("could not read ROM {}: {}", path, e))?
+    };
+
+    let extension = Path::new(path).extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("zip") => unzip(&data).map_err(|e| format!("{}: {}", path, e)),
+        Some("8o") => octo::compile(&String::from_utf8_lossy(&data)).map_err(|e| format!("{}: {}", path, e)),
+        _ => Ok(data),
+    }
+}
+
+// Extract the ROM from a .zip archive. Text files like READMEs are skipped,
+// there has to be exactly one other file.
+pub fn unzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(|e| e.to_string())?;
+
+    let roms: Vec<usize> = (0..archive.len())
+        .filter(|&i| {
+            let name = archive.name_for_index(i).unwrap_or("").to_ascii_lowercase();
+            !name.ends_with('/') && !name.ends_with(".txt") && !name.ends_with(".md")
+        })
+        .collect();
+
+    if roms.len() != 1 {
+        return Err(format!("expected one ROM in the archive, found {}", roms.len()));
+    }
+
+    let mut file = archive.by_index(roms[0]).map_err(|e| e.to_string())?;
+    let mut rom = Vec::new();
+    file.read_to_end(&mut rom).map_err(|e| e.to_string())?;
+
+    Ok(rom)
+}
+
+// Notices when a ROM file changes on disk, by polling its modification time
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Watcher {
+    pub fn new(path: &str) -> Watcher {
+        let path = PathBuf::from(path);
+        let modified = modified(&path);
+
+        Watcher { path, modified }
+    }
+
+    // Whether the file changed since the last call
+    pub fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+
+#[cfg(test)]
+fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in files {
+        writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+#[test]
+fn test_unzip() {
+    let zip = zip_of(&[("README.txt", b"Press 5 to start"), ("game.ch8", &[0x12, 0x00])]);
+
+    assert_eq!(unzip(&zip), Ok(vec![0x12, 0x00]));
+}
+
+#[test]
+fn test_unzip_wrong_count() {
+    let zip = zip_of(&[("a.ch8", &[0x00]), ("b.ch8", &[0x00])]);
+    assert!(unzip(&zip).is_err());
+
+    let zip = zip_of(&[("README.txt", b"")]);
+    assert!(unzip(&zip).is_err());
+
+    assert!(unzip(b"not a zip").is_err());
+}
+
+#[test]
+fn test_read_zip_file() {
+    let path = std::env::temp_dir().join("chip8-test-rom.zip");
+    fs::write(&path, zip_of(&[("game.ch8", &[0xA2, 0x2A])])).unwrap();
+
+    let rom = read(path.to_str().unwrap());
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(rom, Ok(vec![0xA2, 0x2A]));
+}
+
+#[test]
+fn test_read_octo_file() {
+    let path = std::env::temp_dir().join("chip8-test-rom.8o");
+    fs::write(&path, ": main\n  v0 := 0x2A # the answer\n").unwrap();
+
+    let rom = read(path.to_str().unwrap());
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(rom, Ok(vec![0x60, 0x2A]));
+}
+
+#[test]
+fn test_watcher() {
+    let path = std::env::temp_dir().join("chip8-test-watch.ch8");
+    fs::write(&path, [0x00]).unwrap();
+
+    let mut watcher = Watcher::new(path.to_str().unwrap());
+    assert!(!watcher.changed());
+
+    // Pretend the file was last seen a while ago
+    watcher.modified = Some(SystemTime::UNIX_EPOCH);
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+
+    // A missing file, e.g. while an assembler replaces it, isn't a change
+    fs::remove_file(&path).unwrap();
+    assert!(!watcher.changed());
+}
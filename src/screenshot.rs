@@ -0,0 +1,195 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::palette::Palette;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Format {
+    Png,
+    // Binary colour portable pixmap
+    Ppm,
+    // Binary black and white portable bitmap, ignores the palette
+    Pbm,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "png" => Some(Format::Png),
+            "ppm" => Some(Format::Ppm),
+            "pbm" => Some(Format::Pbm),
+            _ => None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Ppm => "ppm",
+            Format::Pbm => "pbm",
+        }
+    }
+}
+
+// Scale the framebuffer up and colour it, returning RGB bytes row by row
+pub fn to_rgb(screen: &[[u8; 64]; 32], palette: &Palette, scale: usize) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(64 * 32 * scale * scale * 3);
+
+    for row in screen.iter() {
+        for _ in 0..scale {
+            for &pixel in row.iter() {
+                let color = palette.colors[pixel as usize & 0b11];
+                for _ in 0..scale {
+                    rgb.extend_from_slice(&color);
+                }
+            }
+        }
+    }
+
+    rgb
+}
+
+pub fn encode(screen: &[[u8; 64]; 32], palette: &Palette, scale: usize, format: Format) -> Vec<u8> {
+    let (width, height) = (64 * scale, 32 * scale);
+    let mut out = Vec::new();
+
+    match format {
+        Format::Ppm => {
+            out.extend_from_slice(format!("P6\n{} {}\n255\n", width, height).as_bytes());
+            out.extend_from_slice(&to_rgb(screen, palette, scale));
+        }
+        Format::Pbm => {
+            // One bit per pixel, set bits are black, rows padded to a byte
+            out.extend_from_slice(format!("P4\n{} {}\n", width, height).as_bytes());
+            for row in screen.iter() {
+                let mut bits = Vec::with_capacity(width / 8 + 1);
+                for col in 0..width {
+                    if col % 8 == 0 {
+                        bits.push(0);
+                    }
+                    if row[col / scale] == 0 {
+                        *bits.last_mut().unwrap() |= 0x80 >> (col % 8);
+                    }
+                }
+                for _ in 0..scale {
+                    out.extend_from_slice(&bits);
+                }
+            }
+        }
+        Format::Png => {
+            let mut encoder = png::Encoder::new(&mut out, width as u32, height as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&to_rgb(screen, palette, scale)).unwrap();
+        }
+    }
+
+    out
+}
+
+pub fn save(screen: &[[u8; 64]; 32], palette: &Palette, scale: usize, path: &Path) -> io::Result<()> {
+    let format = path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(Format::from_name)
+        .unwrap_or(Format::Png);
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&encode(screen, palette, scale, format))?;
+    file.flush()
+}
+
+// A file name for a screenshot or recording of the ROM taken now, like
+// `tetris-20201018-153012.png`
+pub fn file_name(dir: &Path, rom: &str, extension: &str) -> PathBuf {
+    let stem = Path::new(rom)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("chip8");
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+    dir.join(format!("{}-{}.{}", stem, timestamp(now), extension))
+}
+
+// Format seconds since the epoch as a UTC `YYYYMMDD-HHMMSS` timestamp
+pub fn timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    // Civil date from days since the epoch, see Howard Hinnant's
+    // "chrono-Compatible Low-Level Date Algorithms"
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+
+#[test]
+fn test_timestamp() {
+    assert_eq!(timestamp(0), "19700101-000000");
+    assert_eq!(timestamp(951782400), "20000229-000000");
+    assert_eq!(timestamp(1603035012), "20201018-153012");
+}
+
+#[test]
+fn test_file_name() {
+    let name = file_name(Path::new("shots"), "roms/tetris.rom", Format::Ppm.extension());
+
+    assert!(name.starts_with("shots"));
+    let name = name.file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with("tetris-"));
+    assert!(name.ends_with(".ppm"));
+}
+
+#[test]
+fn test_encode_ppm() {
+    let mut screen = [[0; 64]; 32];
+    screen[0][1] = 1;
+
+    let ppm = encode(&screen, &Palette::amber(), 2, Format::Ppm);
+    let header = b"P6\n128 64\n255\n";
+
+    assert_eq!(&ppm[..header.len()], header);
+    assert_eq!(ppm.len(), header.len() + 128 * 64 * 3);
+
+    // Pixel (1, 0) is scaled to cover (2..4, 0..2)
+    let pixels = &ppm[header.len()..];
+    assert_eq!(pixels[0..3], Palette::amber().colors[0]);
+    assert_eq!(pixels[2*3..3*3], Palette::amber().colors[1]);
+    assert_eq!(pixels[(128+3)*3..(128+4)*3], Palette::amber().colors[1]);
+    assert_eq!(pixels[4*3..5*3], Palette::amber().colors[0]);
+}
+
+#[test]
+fn test_encode_pbm() {
+    let mut screen = [[0; 64]; 32];
+    screen[0][0] = 1;
+
+    let pbm = encode(&screen, &Palette::classic(), 1, Format::Pbm);
+    let header = b"P4\n64 32\n";
+
+    assert_eq!(&pbm[..header.len()], header);
+    assert_eq!(pbm.len(), header.len() + 8 * 32);
+    assert_eq!(pbm[header.len()], 0b01111111);
+    assert_eq!(pbm[header.len() + 8], 0xFF);
+}
+
+#[test]
+fn test_encode_png() {
+    let png = encode(&[[0; 64]; 32], &Palette::classic(), 1, Format::Png);
+
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+}
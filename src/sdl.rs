@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+
+use sdl2::{EventPump, Sdl};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::GameController;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Scancode};
+
+use crate::filter::Filter;
+use crate::frontend::{Action, Frontend};
+use crate::graphics::{MemoryWindow, Screen, Scaling, MEMORY_ROWS};
+use crate::keymap::Keymap;
+use crate::memview::{MemoryView, BYTES_PER_ROW};
+use crate::osd::Osd;
+use crate::pacer::NORMAL_STATUS;
+use crate::palette::Palette;
+
+
+pub struct SdlFrontend {
+    sdl_context: Sdl,
+    screen: Screen,
+    event_pump: EventPump,
+    controller_subsystem: GameControllerSubsystem,
+    // Open controllers by joystick id, they're closed when dropped
+    controllers: HashMap<u32, GameController>,
+    keymap: Keymap,
+    osd: Osd,
+    memory_view: MemoryView,
+    // Open while the memory viewer is shown
+    memory_window: Option<MemoryWindow>,
+    memory_dirty: bool,
+    // None if there's no audio device, beeps are printed instead
+    buzzer: Option<AudioDevice<SquareWave>>,
+    dirty: bool, // The window needs redrawing even if the game didn't draw
+    beeping: bool,
+}
+
+impl SdlFrontend {
+    pub fn new() -> SdlFrontend {
+        let sdl_context = sdl2::init().unwrap();
+
+        let buzzer = sdl_context.audio().and_then(|audio| {
+            let spec = AudioSpecDesired { freq: Some(44100), channels: Some(1), samples: None };
+            audio.open_playback(None, &spec, |spec| SquareWave {
+                phase: 0.0,
+                phase_inc: 440.0 / spec.freq as f32,
+                volume: 0.25,
+            })
+        });
+        if let Err(ref e) = buzzer {
+            eprintln!("Could not open audio device: {}", e);
+        }
+
+        SdlFrontend {
+            screen: Screen::new(&sdl_context),
+            event_pump: sdl_context.event_pump().unwrap(),
+            controller_subsystem: sdl_context.game_controller().unwrap(),
+            controllers: HashMap::new(),
+            keymap: Keymap::default(),
+            osd: Osd::new(),
+            memory_view: MemoryView::new(MEMORY_ROWS),
+            memory_window: None,
+            memory_dirty: false,
+            buzzer: buzzer.ok(),
+            dirty: true,
+            beeping: false,
+            sdl_context,
+        }
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    // Set the buzzer's pitch in Hz and volume from 0 to 1
+    pub fn set_audio(&mut self, tone: f32, volume: f32) {
+        if let Some(ref mut buzzer) = self.buzzer {
+            let freq = buzzer.spec().freq as f32;
+            let mut wave = buzzer.lock();
+            wave.phase_inc = tone / freq;
+            wave.volume = volume.clamp(0.0, 1.0);
+        }
+    }
+
+    // Change the window size to a multiple of the framebuffer's
+    pub fn set_window_scale(&mut self, scale: u32) {
+        let (width, height) = self.screen.framebuffer_size();
+        self.screen.set_window_size(width * scale, height * scale);
+        self.dirty = true;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.screen.set_palette(palette);
+        self.dirty = true;
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.screen.set_filter(filter);
+        self.dirty = true;
+    }
+
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.screen.set_scaling(scaling);
+        self.dirty = true;
+    }
+}
+
+impl Default for SdlFrontend {
+    fn default() -> SdlFrontend {
+        SdlFrontend::new()
+    }
+}
+
+impl Frontend for SdlFrontend {
+    fn handle_events(&mut self, keyboard: &mut [bool; 16]) -> Vec<Action> {
+        let mut actions = Vec::new();
+
+        // Redraw while the OSD is up, including once after it's gone
+        if self.osd.is_visible() {
+            self.dirty = true;
+        }
+        self.osd.tick();
+
+        let memory_window_id = self.memory_window.as_ref().map(MemoryWindow::id);
+
+        for event in self.event_pump.poll_iter() {
+            if memory_window_id.is_some() && event.get_window_id() == memory_window_id {
+                match event {
+                    Event::Window { win_event: WindowEvent::Close, ..} |
+                    Event::KeyDown { keycode: Some(Keycode::Escape), ..} |
+                    Event::KeyDown { keycode: Some(Keycode::F3), ..} => {
+                        self.memory_window = None;
+                    }
+                    Event::KeyDown { keycode: Some(keycode), ..} => {
+                        if let Some((address, value)) = memory_key(&mut self.memory_view, keycode) {
+                            actions.push(Action::Poke(address, value));
+                        }
+                        self.memory_dirty = true;
+                    }
+                    Event::Window { win_event: WindowEvent::Exposed, ..} => {
+                        self.memory_dirty = true;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            match event {
+                // With the memory window open closing the main one doesn't quit
+                Event::Quit {..} |
+                Event::Window { win_event: WindowEvent::Close, ..} |
+                Event::KeyDown { keycode: Some(Keycode::Escape), ..} => {
+                    actions.push(Action::Quit);
+                },
+                Event::Window { win_event: WindowEvent::SizeChanged(..), ..} => {
+                    self.screen.resize();
+                    self.dirty = true;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F12), ..} => {
+                    actions.push(Action::Screenshot);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F10), ..} => {
+                    actions.push(Action::ToggleRecording);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F5), ..} => {
+                    actions.push(Action::SoftReset);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F6), ..} => {
+                    actions.push(Action::HardReset);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F7), ..} |
+                Event::KeyDown { keycode: Some(Keycode::Pause), ..} => {
+                    actions.push(Action::TogglePause);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F8), ..} => {
+                    actions.push(Action::FrameAdvance);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F9), ..} => {
+                    actions.push(Action::ToggleSlowMotion);
+                }
+                // Fast forward while Tab is held
+                Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, ..} => {
+                    actions.push(Action::FastForward(true));
+                }
+                Event::KeyUp { keycode: Some(Keycode::Tab), ..} => {
+                    actions.push(Action::FastForward(false));
+                }
+                Event::KeyDown { keycode: Some(Keycode::Tab), ..} => {}
+                Event::KeyDown { keycode: Some(Keycode::F1), ..} => {
+                    self.osd.toggle_stats();
+                    self.dirty = true;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F2), ..} => {
+                    self.osd.toggle_keypad();
+                    self.dirty = true;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F3), ..} => {
+                    self.memory_window = match self.memory_window {
+                        Some(_) => None,
+                        None => Some(MemoryWindow::new(&self.sdl_context)),
+                    };
+                    self.memory_dirty = true;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F11), ..} => {
+                    self.screen.toggle_fullscreen();
+                    self.dirty = true;
+                }
+                Event::KeyDown { keycode, scancode, ..} => {
+                    if let Some(key) = chip8_key(&self.keymap, keycode, scancode) {
+                        keyboard[key] = true;
+                    }
+                }
+                Event::KeyUp { keycode, scancode, ..} => {
+                    if let Some(key) = chip8_key(&self.keymap, keycode, scancode) {
+                        keyboard[key] = false;
+                    }
+                }
+                // Also sent for controllers already plugged in at startup
+                Event::ControllerDeviceAdded { which, ..} => {
+                    match self.controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            println!("Controller connected: {}", controller.name());
+                            self.controllers.insert(controller.instance_id(), controller);
+                        }
+                        Err(e) => eprintln!("Could not open controller: {}", e),
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, ..} => {
+                    if let Some(controller) = self.controllers.remove(&which) {
+                        println!("Controller disconnected: {}", controller.name());
+                    }
+                }
+                Event::ControllerButtonDown { button, ..} => {
+                    if let Some(key) = self.keymap.lookup_button(&button.string()) {
+                        keyboard[key] = true;
+                    }
+                }
+                Event::ControllerButtonUp { button, ..} => {
+                    if let Some(key) = self.keymap.lookup_button(&button.string()) {
+                        keyboard[key] = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.osd.set_keys(keyboard);
+
+        actions
+    }
+
+    fn needs_redraw(&self) -> bool {
+        self.dirty || self.screen.is_animated()
+    }
+
+    fn draw(&mut self, screen: &[[u8; 64]; 32]) {
+        let (width, height) = self.screen.osd_size();
+        self.screen.draw(screen, &self.osd.layout(width, height));
+        self.dirty = false;
+    }
+
+    fn beep(&mut self, playing: bool) {
+        match self.buzzer {
+            Some(ref buzzer) if playing && !self.beeping => buzzer.resume(),
+            Some(ref buzzer) if !playing && self.beeping => buzzer.pause(),
+            None if playing && !self.beeping => println!("BEEP!"),
+            _ => {}
+        }
+        self.beeping = playing;
+    }
+
+    fn set_status(&mut self, status: &str) {
+        self.screen.set_status(status);
+        self.osd.set_status(if status == NORMAL_STATUS { "" } else { status });
+        self.dirty = true;
+    }
+
+    fn show_message(&mut self, text: &str) {
+        self.osd.message(text);
+        self.dirty = true;
+    }
+
+    fn set_stats(&mut self, fps: f32, ips: f32) {
+        self.osd.set_stats(fps, ips);
+    }
+
+    fn show_memory(&mut self, memory: &[u8; 4096], pc: u16, index: u16) {
+        if self.memory_view.update(memory, pc, index) {
+            self.memory_dirty = true;
+        }
+
+        if let Some(ref mut window) = self.memory_window {
+            if self.memory_dirty {
+                window.draw(&self.memory_view);
+                self.memory_dirty = false;
+            }
+        }
+    }
+}
+
+// The same lookup for presses and releases, so they always pair up
+fn chip8_key(keymap: &Keymap, keycode: Option<Keycode>, scancode: Option<Scancode>) -> Option<usize> {
+    let keycode = keycode.map(|keycode| keycode.name());
+
+    keymap.lookup(keycode.as_deref(), scancode.map(|scancode| scancode.name()))
+}
+
+// Keys in the memory window: arrows and page up/down move, hex digits type
+// in a byte, P and I jump to pc and the index register
+fn memory_key(view: &mut MemoryView, keycode: Keycode) -> Option<(u16, u8)> {
+    let row = BYTES_PER_ROW as i32;
+
+    match keycode {
+        Keycode::Left => view.move_cursor(-1),
+        Keycode::Right => view.move_cursor(1),
+        Keycode::Up => view.move_cursor(-row),
+        Keycode::Down => view.move_cursor(row),
+        Keycode::PageUp => view.move_cursor(-row * MEMORY_ROWS as i32),
+        Keycode::PageDown => view.move_cursor(row * MEMORY_ROWS as i32),
+        Keycode::P => view.goto_pc(),
+        Keycode::I => view.goto_index(),
+        _ => {
+            let digit = u8::from_str_radix(&keycode.name(), 16).ok().filter(|&digit| digit < 16)?;
+            return view.type_digit(digit);
+        }
+    }
+
+    None
+}
+
+// The buzzer's tone
+struct SquareWave {
+    phase: f32,
+    phase_inc: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase < 0.5 { self.volume } else { -self.volume };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
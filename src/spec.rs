@@ -0,0 +1,309 @@
+// A deliberately simple reference interpreter for differential testing.
+//
+// This decodes raw instruction words nibble by nibble without going through
+// `Opcode`, so a decoding or execution bug in the emulator can't be hidden by
+// sharing code with the thing it's being checked against.
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::chip8::Chip8;
+use crate::quirks::Quirks;
+
+#[allow(non_snake_case)]
+#[derive(Clone)]
+pub struct SpecState {
+    pub memory: [u8; 4096],
+    pub stack: [u16; 16],
+    pub V: [u8; 16],
+    pub I: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub screen: [[u8; 64]; 32],
+    pub keyboard: [bool; 16],
+    pub vblank_wait: bool,
+}
+
+impl SpecState {
+    pub fn random(rng: &mut StdRng) -> SpecState {
+        let mut memory = [0; 4096];
+        rng.fill_bytes(&mut memory);
+
+        let mut screen = [[0; 64]; 32];
+        for row in screen.iter_mut() {
+            rng.fill_bytes(row);
+            for pixel in row.iter_mut() {
+                *pixel &= 1;
+            }
+        }
+
+        let mut state = SpecState {
+            memory,
+            stack: [0; 16],
+            V: [0; 16],
+            I: 0,
+            pc: 0x200,
+            sp: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            screen,
+            keyboard: [false; 16],
+            vblank_wait: false,
+        };
+        state.randomize_registers(rng);
+
+        state
+    }
+
+    // Filling memory and the screen is slow, so cases share those and only
+    // get fresh registers.
+    pub fn randomize_registers(&mut self, rng: &mut StdRng) {
+        for slot in self.stack.iter_mut() {
+            *slot = rng.gen_range(0x200, 0x1000);
+        }
+
+        for key in self.keyboard.iter_mut() {
+            *key = rng.gen();
+        }
+
+        self.V = rng.gen();
+        self.I = rng.gen();
+        self.pc = rng.gen_range(0x200, 0x1000 - 4) & !1;
+        self.sp = rng.gen_range(1, 15);
+        self.delay_timer = rng.gen();
+        self.sound_timer = rng.gen();
+    }
+}
+
+// Memory is 4 KiB and addresses past the end wrap around to the start.
+fn wrap(address: usize) -> usize {
+    address % 4096
+}
+
+// Execute a single instruction word against the state.
+pub fn step(s: &mut SpecState, word: u16, quirks: &Quirks) {
+    let a = (word >> 12) & 0xF;
+    let x = ((word >> 8) & 0xF) as usize;
+    let y = ((word >> 4) & 0xF) as usize;
+    let n = word & 0xF;
+    let kk = (word & 0xFF) as u8;
+    let nnn = word & 0xFFF;
+
+    s.pc += 2;
+    let mut skip = false;
+
+    match (a, n) {
+        (0x0, _) if word == 0x00E0 => s.screen = [[0; 64]; 32],
+        (0x0, _) if word == 0x00EE => {
+            s.pc = s.stack[s.sp as usize];
+            s.sp -= 1;
+        }
+        (0x0, _) => {}
+        (0x1, _) => s.pc = nnn,
+        (0x2, _) => {
+            s.sp += 1;
+            s.stack[s.sp as usize] = s.pc;
+            s.pc = nnn;
+        }
+        (0x3, _) => skip = s.V[x] == kk,
+        (0x4, _) => skip = s.V[x] != kk,
+        (0x5, 0x0) => skip = s.V[x] == s.V[y],
+        (0x6, _) => s.V[x] = kk,
+        (0x7, _) => s.V[x] = s.V[x].wrapping_add(kk),
+        (0x8, 0x0) => s.V[x] = s.V[y],
+        (0x8, 0x1) => s.V[x] |= s.V[y],
+        (0x8, 0x2) => s.V[x] &= s.V[y],
+        (0x8, 0x3) => s.V[x] ^= s.V[y],
+        (0x8, 0x4) => {
+            let (vx, vy) = (s.V[x], s.V[y]);
+            s.V[x] = vx.wrapping_add(vy);
+            s.V[0xF] = if vx as u16 + vy as u16 > 0xFF { 1 } else { 0 };
+        }
+        (0x8, 0x5) => {
+            let (vx, vy) = (s.V[x], s.V[y]);
+            s.V[x] = vx.wrapping_sub(vy);
+            s.V[0xF] = if vx >= vy { 1 } else { 0 };
+        }
+        (0x8, 0x6) => {
+            let vx = s.V[x];
+            s.V[x] = vx >> 1;
+            s.V[0xF] = vx & 1;
+        }
+        (0x8, 0x7) => {
+            let (vx, vy) = (s.V[x], s.V[y]);
+            s.V[x] = vy.wrapping_sub(vx);
+            s.V[0xF] = if vy >= vx { 1 } else { 0 };
+        }
+        (0x8, 0xE) => {
+            let vx = s.V[x];
+            s.V[x] = vx << 1;
+            s.V[0xF] = vx >> 7;
+        }
+        (0x9, 0x0) => skip = s.V[x] != s.V[y],
+        (0xA, _) => s.I = nnn,
+        (0xB, _) => s.pc = nnn + s.V[0] as u16,
+        (0xD, _) => {
+            let mut collision = 0;
+            for row in 0..n as usize {
+                let sprite = s.memory[wrap(s.I as usize + row)];
+                for col in 0..8 {
+                    let bit = (sprite >> (7 - col)) & 1;
+                    let mut px = s.V[x] as usize % 64 + col;
+                    let mut py = s.V[y] as usize % 32 + row;
+                    if (px >= 64 && quirks.clip_x) || (py >= 32 && quirks.clip_y) {
+                        continue;
+                    }
+                    px %= 64;
+                    py %= 32;
+
+                    if bit == 1 && s.screen[py][px] == 1 {
+                        collision = 1;
+                    }
+                    s.screen[py][px] ^= bit;
+                }
+            }
+            s.V[0xF] = collision;
+            s.vblank_wait = quirks.display_wait;
+        }
+        (0xE, _) if kk == 0x9E => skip = s.keyboard[s.V[x] as usize],
+        (0xE, _) if kk == 0xA1 => skip = !s.keyboard[s.V[x] as usize],
+        (0xF, _) if kk == 0x07 => s.V[x] = s.delay_timer,
+        (0xF, _) if kk == 0x15 => s.delay_timer = s.V[x],
+        (0xF, _) if kk == 0x18 => s.sound_timer = s.V[x],
+        (0xF, _) if kk == 0x1E => s.I = s.I.wrapping_add(s.V[x] as u16),
+        (0xF, _) if kk == 0x29 => s.I = s.V[x] as u16 * 5,
+        (0xF, _) if kk == 0x33 => {
+            let i = s.I as usize;
+            s.memory[wrap(i)] = s.V[x] / 100;
+            s.memory[wrap(i + 1)] = s.V[x] / 10 % 10;
+            s.memory[wrap(i + 2)] = s.V[x] % 10;
+        }
+        (0xF, _) if kk == 0x55 => {
+            for r in 0..=x {
+                s.memory[wrap(s.I as usize + r)] = s.V[r];
+            }
+        }
+        (0xF, _) if kk == 0x65 => {
+            for r in 0..=x {
+                s.V[r] = s.memory[wrap(s.I as usize + r)];
+            }
+        }
+        _ => {}
+    }
+
+    if skip {
+        s.pc += 2;
+    }
+}
+
+// Whether the differential test should skip this word. RND is random and
+// Fx0A blocks waiting on real input, so neither can be compared directly.
+fn untestable(word: u16) -> bool {
+    let a = word >> 12;
+    let kk = word & 0xFF;
+
+    a == 0xC || (a == 0xF && kk == 0x0A)
+}
+
+// Bring the operands of the instruction into the range the hardware defines,
+// e.g. key and font digit lookups only make sense for values 0-F.
+fn constrain(s: &mut SpecState, word: u16) {
+    let a = word >> 12;
+    let x = ((word >> 8) & 0xF) as usize;
+    let kk = word & 0xFF;
+
+    if a == 0xE || (a == 0xF && kk == 0x29) {
+        s.V[x] &= 0xF;
+    }
+}
+
+fn load(chip8: &mut Chip8, s: &SpecState) {
+    chip8.memory = s.memory;
+    chip8.stack = s.stack;
+    chip8.V = s.V;
+    chip8.I = s.I;
+    chip8.pc = s.pc;
+    chip8.sp = s.sp;
+    chip8.delay_timer = s.delay_timer;
+    chip8.sound_timer = s.sound_timer;
+    chip8.keyboard = s.keyboard;
+    chip8.vblank_wait = s.vblank_wait;
+    chip8.screen = s.screen;
+}
+
+fn assert_agrees(chip8: &Chip8, s: &SpecState, word: u16) {
+    assert_eq!(chip8.V, s.V, "V registers differ after {:04X}", word);
+    assert_eq!(chip8.I, s.I, "I differs after {:04X}", word);
+    assert_eq!(chip8.pc, s.pc, "pc differs after {:04X}", word);
+    assert_eq!(chip8.sp, s.sp, "sp differs after {:04X}", word);
+    assert_eq!(chip8.stack, s.stack, "stack differs after {:04X}", word);
+    assert_eq!(chip8.delay_timer, s.delay_timer, "delay timer differs after {:04X}", word);
+    assert_eq!(chip8.sound_timer, s.sound_timer, "sound timer differs after {:04X}", word);
+    assert_eq!(chip8.vblank_wait, s.vblank_wait, "vblank wait differs after {:04X}", word);
+    assert!(chip8.memory[..] == s.memory[..], "memory differs after {:04X}", word);
+    for row in 0..32 {
+        assert_eq!(chip8.screen[row], s.screen[row], "screen row {} differs after {:04X}", row, word);
+    }
+}
+
+fn check(chip8: &mut Chip8, base: &SpecState, rng: &mut StdRng, word: u16, quirks: &Quirks) {
+    let mut state = base.clone();
+    state.randomize_registers(rng);
+    constrain(&mut state, word);
+    load(chip8, &state);
+
+    chip8.opcode = crate::opcode::Opcode::from_bytes(word);
+    chip8.execute_opcode();
+    step(&mut state, word, quirks);
+
+    assert_agrees(chip8, &state, word);
+}
+
+#[test]
+fn test_spec_agrees_with_emulator() {
+    let mut chip8 = Chip8::new();
+    let mut rng = StdRng::seed_from_u64(0xC8);
+    let mut base = SpecState::random(&mut rng);
+
+    for quirks in Quirks::profiles().iter() {
+        chip8.set_quirks(*quirks);
+
+        let mut checked = 0;
+        while checked < 20_000 {
+            let word: u16 = rng.gen();
+            if untestable(word) {
+                continue;
+            }
+
+            if checked % 64 == 0 {
+                base = SpecState::random(&mut rng);
+            }
+
+            check(&mut chip8, &base, &mut rng, word, quirks);
+            checked += 1;
+        }
+    }
+}
+
+#[test]
+fn test_spec_agrees_on_every_word() {
+    let mut chip8 = Chip8::new();
+    let mut rng = StdRng::seed_from_u64(0x200);
+    let mut base = SpecState::random(&mut rng);
+
+    for quirks in Quirks::profiles().iter() {
+        chip8.set_quirks(*quirks);
+
+        for word in 0..=0xFFFF_u16 {
+            if word % 64 == 0 {
+                base = SpecState::random(&mut rng);
+            }
+
+            if !untestable(word) {
+                check(&mut chip8, &base, &mut rng, word, quirks);
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::opcode::Opcode;
+
+// How many instructions run each 60Hz frame.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Timing {
+    // A fixed number of instructions per frame, the emulator's original
+    // behaviour is one
+    Fixed(u32),
+    // A number of instructions per second, spread over the frames with
+    // what doesn't divide evenly carried over to the next
+    PerSecond(u32),
+    // Each instruction costs what it did on the COSMAC VIP, out of a fixed
+    // budget of machine cycles per frame
+    Vip,
+}
+
+// The most instructions per second `PerSecond` runs, far more than any game
+// needs but little enough to finish a frame in time
+pub const MAX_IPS: u32 = 1_000_000;
+
+// The VIP's 1802 runs at 1.7609 MHz with 8 clocks per machine cycle,
+// giving roughly 3668 machine cycles per 60Hz frame.
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+// Cycles stolen every frame by the display interrupt: 1024 DMA cycles to
+// feed the CDP1861 plus the interrupt routine itself, which also updates
+// the timers.
+pub const VIP_INTERRUPT_CYCLES: u32 = 1024 + 46;
+
+// Every instruction pays for the interpreter's fetch and decode loop.
+const FETCH_CYCLES: u32 = 40;
+
+// Approximate machine cycle cost of an instruction on the VIP interpreter.
+// Some costs depend on register values, e.g. DRW on the sprite's alignment.
+#[allow(non_snake_case)]
+pub fn vip_cycles(opcode: &Opcode, V: &[u8; 16]) -> u32 {
+    let cost = match *opcode {
+        Opcode::SYS(_) => 0,
+        Opcode::CLS => 3078,
+        Opcode::RET => 10,
+        Opcode::JP(_) => 12,
+        Opcode::CALL(_) => 26,
+        Opcode::SE(..) | Opcode::SNE(..) => 10,
+        Opcode::SE_V(..) | Opcode::SNE_V(..) => 14,
+        Opcode::LD(..) => 6,
+        Opcode::ADD(..) => 10,
+        Opcode::LD_V(..) |
+        Opcode::OR(..) |
+        Opcode::AND(..) |
+        Opcode::XOR(..) |
+        Opcode::ADD_V(..) |
+        Opcode::SUB(..) |
+        Opcode::SHR(..) |
+        Opcode::SUBN(..) |
+        Opcode::SHL(..) => 44,
+        Opcode::LD_I(_) => 12,
+        Opcode::JP_V(_) => 22,
+        Opcode::RND(..) => 36,
+        Opcode::DRW(x, _, n) => {
+            // Sprites that don't start on a byte boundary are shifted into
+            // two bytes of display memory, which costs extra per row
+            let per_row = if V[x] & 0b111 == 0 { 46 } else { 64 };
+            26 + n as u32 * per_row
+        }
+        Opcode::SKP(_) | Opcode::SKNP(_) => 14,
+        Opcode::LD_V_DT(_) => 10,
+        Opcode::LD_K(_) => 0,
+        Opcode::LD_DT_V(_) | Opcode::LD_ST(_) => 10,
+        Opcode::ADD_I(_) => 16,
+        Opcode::LD_F(_) => 20,
+        Opcode::LD_B(x) => 80 + V[x] as u32 / 10 * 16,
+        Opcode::LD_I_V(x) | Opcode::LD_V_I(x) => 18 + 14 * (x as u32 + 1),
+        Opcode::UNDEFINED => 0,
+    };
+
+    FETCH_CYCLES + cost
+}
+
+
+#[test]
+fn test_vip_cycles_fetch_overhead() {
+    let regs = [0; 16];
+
+    assert_eq!(vip_cycles(&Opcode::UNDEFINED, &regs), FETCH_CYCLES);
+    assert_eq!(vip_cycles(&Opcode::LD(0, 0), &regs), FETCH_CYCLES + 6);
+}
+
+#[test]
+fn test_vip_cycles_drw_alignment() {
+    let mut regs = [0; 16];
+
+    regs[0] = 8;
+    let aligned = vip_cycles(&Opcode::DRW(0, 1, 5), &regs);
+    regs[0] = 9;
+    let unaligned = vip_cycles(&Opcode::DRW(0, 1, 5), &regs);
+
+    assert!(unaligned > aligned);
+}
+
+#[test]
+fn test_vip_cycles_drw_size() {
+    let regs = [0; 16];
+
+    let small = vip_cycles(&Opcode::DRW(0, 1, 1), &regs);
+    let large = vip_cycles(&Opcode::DRW(0, 1, 15), &regs);
+
+    assert_eq!(large - small, 14 * 46);
+}
+
+#[test]
+fn test_vip_cycles_register_dump() {
+    let regs = [0; 16];
+
+    let one = vip_cycles(&Opcode::LD_I_V(0), &regs);
+    let all = vip_cycles(&Opcode::LD_I_V(0xF), &regs);
+
+    assert_eq!(all - one, 15 * 14);
+}
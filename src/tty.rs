@@ -0,0 +1,340 @@
+use std::io::prelude::*;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use crate::frontend::{Action, Frontend};
+use crate::keymap::Keymap;
+
+// Terminals only report key presses, so a key counts as held for a while
+// after each press. The first press has to last until the terminal's key
+// repeat kicks in, after that repeats arrive every couple of frames.
+const PRESS_FRAMES: u32 = 15;
+const REPEAT_FRAMES: u32 = 6;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TtyMode {
+    // Two pixels per character using half block characters
+    HalfBlock,
+    // Eight pixels per character using braille patterns
+    Braille,
+}
+
+pub struct TtyFrontend {
+    mode: TtyMode,
+    input: Receiver<u8>,
+    held: HeldKeys,
+    keymap: Keymap,
+    saved_stty: String,
+    beeping: bool,
+    fast_forward: bool,
+    // Printed once the terminal is restored, it would garble the picture
+    log: Vec<String>,
+}
+
+impl TtyFrontend {
+    pub fn new(mode: TtyMode) -> TtyFrontend {
+        let saved_stty = stty(&["-g"]).trim().to_string();
+        stty(&["raw", "-echo"]);
+
+        // Reading stdin blocks, so it happens on its own thread
+        let (sender, input) = channel();
+        thread::spawn(move || {
+            let stdin = std::io::stdin();
+            for byte in stdin.lock().bytes() {
+                match byte {
+                    Ok(byte) => if sender.send(byte).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        // Clear the terminal and hide the cursor
+        print!("\x1b[2J\x1b[?25l");
+        std::io::stdout().flush().unwrap();
+
+        TtyFrontend {
+            mode,
+            input,
+            held: HeldKeys::new(),
+            keymap: Keymap::default(),
+            saved_stty,
+            beeping: false,
+            fast_forward: false,
+            log: Vec::new(),
+        }
+    }
+
+    // Only host keys named by a single character work in a terminal
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+}
+
+impl Drop for TtyFrontend {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\r\n");
+        std::io::stdout().flush().unwrap();
+        stty(&[&self.saved_stty]);
+
+        for line in &self.log {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+impl Frontend for TtyFrontend {
+    fn handle_events(&mut self, keyboard: &mut [bool; 16]) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let bytes: Vec<u8> = self.input.try_iter().collect();
+
+        self.held.tick();
+
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                // Ctrl-C, raw mode means it doesn't raise a signal
+                0x03 => actions.push(Action::Quit),
+                // Ctrl-P
+                0x10 => actions.push(Action::Screenshot),
+                // Ctrl-R
+                0x12 => actions.push(Action::ToggleRecording),
+                // Ctrl-T and Ctrl-Y
+                0x14 => actions.push(Action::SoftReset),
+                0x19 => actions.push(Action::HardReset),
+                // Ctrl-O, Ctrl-N and Ctrl-W. Terminals can't tell when a key
+                // is let go, so Ctrl-F toggles fast forward instead.
+                0x0F => actions.push(Action::TogglePause),
+                0x0E => actions.push(Action::FrameAdvance),
+                0x17 => actions.push(Action::ToggleSlowMotion),
+                0x06 => {
+                    self.fast_forward = !self.fast_forward;
+                    actions.push(Action::FastForward(self.fast_forward));
+                }
+                0x1B => {
+                    // Escape on its own quits, escape sequences for keys we
+                    // don't use are skipped
+                    if i + 1 < bytes.len() && (bytes[i+1] == b'[' || bytes[i+1] == b'O') {
+                        i += 2;
+                        while i < bytes.len() && !(0x40..=0x7E).contains(&bytes[i]) {
+                            i += 1;
+                        }
+                    } else {
+                        actions.push(Action::Quit);
+                    }
+                }
+                byte => {
+                    if let Some(key) = self.keymap.lookup_char(byte) {
+                        self.held.press(key);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        *keyboard = self.held.keyboard();
+        actions
+    }
+
+    fn needs_redraw(&self) -> bool {
+        false
+    }
+
+    fn draw(&mut self, screen: &[[u8; 64]; 32]) {
+        let frame = match self.mode {
+            TtyMode::HalfBlock => render_half_blocks(screen),
+            TtyMode::Braille => render_braille(screen),
+        };
+
+        print!("\x1b[H{}", frame);
+        std::io::stdout().flush().unwrap();
+    }
+
+    fn beep(&mut self, playing: bool) {
+        if playing && !self.beeping {
+            print!("\x07");
+            std::io::stdout().flush().unwrap();
+        }
+        self.beeping = playing;
+    }
+
+    fn set_status(&mut self, status: &str) {
+        self.print_below(1, status);
+    }
+
+    fn show_message(&mut self, text: &str) {
+        self.print_below(2, text);
+    }
+
+    fn log(&mut self, text: &str) {
+        self.log.push(text.to_string());
+    }
+
+    fn log_error(&mut self, text: &str) {
+        self.log.push(text.to_string());
+    }
+}
+
+impl TtyFrontend {
+    // Replace a line below the picture, counting from 1
+    fn print_below(&self, line: usize, text: &str) {
+        let row = match self.mode {
+            TtyMode::HalfBlock => 32 / 2 + line,
+            TtyMode::Braille => 32 / 4 + line,
+        };
+
+        print!("\x1b[{};1H{}\x1b[K", row, text);
+        std::io::stdout().flush().unwrap();
+    }
+}
+
+fn stty(args: &[&str]) -> String {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .unwrap();
+
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+// Emulates key releases by letting presses expire after a few frames
+struct HeldKeys {
+    frames: [u32; 16],
+}
+
+impl HeldKeys {
+    fn new() -> HeldKeys {
+        HeldKeys { frames: [0; 16] }
+    }
+
+    fn press(&mut self, key: usize) {
+        self.frames[key] = if self.frames[key] > 0 { REPEAT_FRAMES } else { PRESS_FRAMES };
+    }
+
+    // Advance one frame
+    fn tick(&mut self) {
+        for frames in self.frames.iter_mut() {
+            *frames = frames.saturating_sub(1);
+        }
+    }
+
+    fn keyboard(&self) -> [bool; 16] {
+        let mut keyboard = [false; 16];
+        for (key, frames) in self.frames.iter().enumerate() {
+            keyboard[key] = *frames > 0;
+        }
+        keyboard
+    }
+}
+
+fn render_half_blocks(screen: &[[u8; 64]; 32]) -> String {
+    let mut out = String::new();
+
+    for rows in screen.chunks(2) {
+        for (top, bottom) in rows[0].iter().zip(rows[1].iter()) {
+            out.push(match (*top != 0, *bottom != 0) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            });
+        }
+        out.push_str("\r\n");
+    }
+
+    out
+}
+
+fn render_braille(screen: &[[u8; 64]; 32]) -> String {
+    // Bit for each dot in a 2x4 braille cell, indexed by [row][col]
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+    let mut out = String::new();
+
+    for rows in screen.chunks(4) {
+        for col in (0..64).step_by(2) {
+            let mut cell = 0;
+            for (r, row) in rows.iter().enumerate() {
+                for c in 0..2 {
+                    if row[col + c] != 0 {
+                        cell |= DOTS[r][c];
+                    }
+                }
+            }
+            out.push(std::char::from_u32(0x2800 + cell).unwrap());
+        }
+        out.push_str("\r\n");
+    }
+
+    out
+}
+
+
+#[test]
+fn test_default_keys() {
+    let keymap = Keymap::default();
+
+    assert_eq!(keymap.lookup_char(b'1'), Some(0x0));
+    assert_eq!(keymap.lookup_char(b'V'), Some(0xF));
+    assert_eq!(keymap.lookup_char(b'p'), None);
+}
+
+#[test]
+fn test_held_keys_release() {
+    let mut held = HeldKeys::new();
+    held.press(3);
+
+    for _ in 0..PRESS_FRAMES - 1 {
+        held.tick();
+        assert!(held.keyboard()[3]);
+    }
+
+    held.tick();
+    assert!(!held.keyboard()[3]);
+}
+
+#[test]
+fn test_held_keys_repeat() {
+    let mut held = HeldKeys::new();
+    held.press(3);
+    held.tick();
+    held.press(3);
+
+    for _ in 0..REPEAT_FRAMES {
+        held.tick();
+    }
+
+    assert!(!held.keyboard()[3]);
+}
+
+#[test]
+fn test_render_half_blocks() {
+    let mut screen = [[0; 64]; 32];
+    screen[0][0] = 1;
+    screen[1][1] = 1;
+    screen[0][2] = 1;
+    screen[1][2] = 1;
+
+    let out = render_half_blocks(&screen);
+    let lines: Vec<&str> = out.split("\r\n").collect();
+
+    assert_eq!(lines.len(), 17);
+    assert!(lines[0].starts_with("▀▄█ "));
+    assert_eq!(lines[0].chars().count(), 64);
+}
+
+#[test]
+fn test_render_braille() {
+    let mut screen = [[0; 64]; 32];
+    screen[0][0] = 1;
+    screen[3][1] = 1;
+
+    let out = render_braille(&screen);
+    let lines: Vec<&str> = out.split("\r\n").collect();
+
+    assert_eq!(lines.len(), 9);
+    assert_eq!(lines[0].chars().count(), 32);
+    assert_eq!(lines[0].chars().next(), Some('\u{2881}'));
+    assert_eq!(lines[1].chars().next(), Some('\u{2800}'));
+}